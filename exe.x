@@ -0,0 +1,102 @@
+/* MZ .EXE programs. The load image is laid out like a COM program, starting
+   at 100h right after the PSP, and the startup code in .exe_startup (see
+   lib.rs) points CS, DS and SS at the PSP. Addresses are the same offsets as
+   with link.x. Read only data, data, .bss and the heap can go past the first
+   64KB, but code and the stack can't: IP and SP are 16 bits.
+
+   The stack isn't in the file. The header's SS:SP is a stack segment of its
+   own after .bss, which DOS allocates along with the image. It's only used
+   until the startup code has moved the image up by STACK_SIZE, to where it's
+   linked. The gap that leaves below the code is the program's stack, with SS
+   set to the PSP like DS: pointers to locals are used with DS, so the stack
+   has to be addressable from DS as well.
+
+   The EXE header is built here, at the start of the file but outside of the
+   loaded image. Code that needs a segment fixed up at load time adds a
+   relocation (offset, segment) to .exe_relocations, relative to the image. */
+
+ENTRY(_exe_start)
+
+STACK_SIZE = 0x4000;
+/* For the far jump, and interrupts, before the startup code switches stacks */
+LOADER_STACK_SIZE = 0x100;
+
+MEMORY {
+  file  : org = 0, len = 0xA0000
+  image : org = 0x100, len = 0xA0000 - 0x100
+}
+
+SECTIONS {
+  .header : {
+    SHORT(0x5A4D)                                   /* "MZ" */
+    SHORT(_file_size % 512)                         /* Bytes used in the last page */
+    SHORT((_file_size + 511) / 512)                 /* 512 byte pages in the file */
+    SHORT(SIZEOF(.relocations) / 4)                 /* Relocation count */
+    SHORT(_header_size / 16)                        /* Header size in paragraphs */
+    SHORT((_program_end - _load_end + 15) / 16)     /* Paragraphs needed after the loaded image */
+    SHORT(0xFFFF)                                   /* Most paragraphs wanted, all of them like a COM */
+    SHORT((_loader_stack_bottom - 0x100) / 16)      /* SS, relative to the image */
+    SHORT(LOADER_STACK_SIZE)                        /* SP */
+    SHORT(0)                                        /* Checksum, ignored by DOS */
+    SHORT(_exe_start - 0x100)                       /* IP */
+    SHORT(0)                                        /* CS, relative to the image */
+    SHORT(LOADADDR(.relocations))                   /* Offset of the relocation table */
+    SHORT(0)                                        /* Overlay number */
+  } > file
+
+  .relocations : {
+    KEEP(*(.exe_relocations))
+    . = ALIGN(16);
+  } > file
+  _header_size = LOADADDR(.relocations) + SIZEOF(.relocations);
+
+  /* Runs where DOS loads it, so it comes first and isn't moved */
+  .exe_startup : {
+    KEEP(*(.exe_startup))
+    . = ALIGN(16);
+  } > image AT> file
+
+  .stack (NOLOAD) : {
+    _stack_bottom = .;
+    . += STACK_SIZE;
+    _stack_top = .;
+  } > image
+
+  .text : {
+    *(.startup)
+    *(.text .text.*)
+  } > image AT> file
+  .rodata : { *(.rodata .rodata.*) } > image AT> file
+  /* Tests registered with test_case!, see testing.rs */
+  .tests  : {
+    _tests_start = .;
+    KEEP(*(.tests))
+    _tests_end = .;
+  } > image AT> file
+  .data   : { *(.data .data.*) } > image AT> file
+  _image_end = .;
+  _file_size = LOADADDR(.data) + SIZEOF(.data);
+
+  /* What the startup code moves up by STACK_SIZE: everything after itself,
+     from where DOS loaded it (the file offset less the header, from 100h) */
+  _move_start = ADDR(.text);
+  _move_size = _image_end - _move_start;
+  _load_end = 0x100 + _file_size - _header_size;
+
+  .bss (NOLOAD) : {
+    _bss_start = .;
+    *(.bss .bss.*)
+    _bss_end = .;
+  } > image
+
+  .loader_stack (NOLOAD) : {
+    . = ALIGN(16);
+    _loader_stack_bottom = .;
+    . += LOADER_STACK_SIZE;
+  } > image
+  _heap = ALIGN(4);
+  _program_end = ALIGN(16);
+
+  ASSERT(_stack_top <= 0x10000, "the stack has to end within the first 64KB")
+  ASSERT(ADDR(.text) + SIZEOF(.text) <= 0x10000, "code has to end within the first 64KB")
+}
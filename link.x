@@ -7,8 +7,19 @@ MEMORY {
 SECTIONS {
   .text   : { *(.startup) *(.text .text.*) }   > dos
   .rodata : { *(.rodata .rodata.*) } > dos
+  /* Tests registered with test_case!, see testing.rs */
+  .tests  : {
+    _tests_start = .;
+    KEEP(*(.tests))
+    _tests_end = .;
+  } > dos
   .data   : { *(.data) }   > dos
   .bss    : { *(.bss) }    > dos
   .stack  : { *(.stack) }  > dos
   _heap = ALIGN(4);
+  /* The stack starts at the top of the segment */
+  _program_end = 0x10000;
+
+  /* Startup code for exe.x */
+  /DISCARD/ : { *(.exe_startup) *(.exe_relocations) }
 }
@@ -1 +1,2 @@
+pub mod memory;
 pub mod video;
\ No newline at end of file
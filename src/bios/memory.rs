@@ -0,0 +1,61 @@
+//! BIOS Memory Services
+//! ======================================================================
+//!
+//! The BIOS knows how much conventional memory is installed. Some of it may
+//! be taken by the extended BIOS data area (EBDA), which sits at the very top
+//! of conventional memory and must not be overwritten.
+//!
+//! Implementation progress:
+//!
+//! | Op   | Function call                                           | Status |
+//! |------|---------------------------------------------------------|--------|
+//! | 12   | [Get conventional memory size](conventional_memory_kb)  |   ✔️    |
+//!
+//! References:
+//! * [BIOS Data Area (OS Dev)](https://wiki.osdev.org/Memory_Map_(x86)#BIOS_Data_Area_.28BDA.29)
+//! * [INT 12h (Ralf Brown's Interrupt List)](https://www.ctyme.com/intr/rb-0598.htm)
+
+use core::arch::asm;
+
+use crate::dos::far_ptr::FarPtr;
+
+/// Segment of the BIOS data area
+const BIOS_DATA_SEGMENT: u16 = 0x40;
+/// Offset in the BIOS data area of the EBDA segment word
+const EBDA_SEGMENT_OFFSET: u16 = 0x0E;
+/// Conventional memory ends where video memory starts
+const VIDEO_MEMORY_SEGMENT: u16 = 0xA000;
+
+/// Kilobytes of conventional memory usable by DOS, which excludes the EBDA
+/// on most machines
+pub fn conventional_memory_kb() -> u16 {
+    let kilobytes: u16;
+
+    unsafe {
+        asm!("int 0x12",
+            out("ax") kilobytes,
+        );
+    }
+
+    kilobytes
+}
+
+/// Segment just past the conventional memory reported by
+/// [conventional_memory_kb]
+pub fn conventional_memory_top() -> u16 {
+    // 64 paragraphs to a kilobyte
+    (conventional_memory_kb() as u32 * 64).min(VIDEO_MEMORY_SEGMENT as u32) as u16
+}
+
+/// Segment of the extended BIOS data area, if the BIOS set one up. Machines
+/// without one usually leave the pointer at 0, older ones may have anything
+/// there, so only values inside conventional memory are believed
+pub fn ebda_segment() -> Option<u16> {
+    let segment = FarPtr::new(BIOS_DATA_SEGMENT, EBDA_SEGMENT_OFFSET).read_word();
+
+    if segment == 0 || segment >= VIDEO_MEMORY_SEGMENT {
+        return None;
+    }
+
+    Some(segment)
+}
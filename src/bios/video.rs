@@ -53,7 +53,7 @@
 
 use core::arch::asm;
 
-use crate::dos::misc;
+use crate::dos::far_ptr::{self, BufferLocation, BufferRegisters, FarPtr};
 
 /// Various video modes that can be sent to [set_video]. Check that function
 /// for usage and warnings.
@@ -307,26 +307,14 @@ pub fn pixel(page: u8, x: u16, y: u16) -> u8 {
 
 // Set all palette registers
 pub fn set_vga_dac(colours: &[VgaDacColour], start: u32) {
-
-    let (segment, offset) = misc::ptr_to_segments(colours.as_ptr() as u32);
+    let registers = BufferRegisters {
+        ax: 0x1012,
+        bx: start as u16,
+        cx: colours.len() as u16,
+        ..Default::default()
+    };
 
     unsafe {
-        asm!(
-            "mov ax, es",
-            "push ax",          // Preserve data extra segment register
-            "add ax, di",
-            "mov es, ax",       // Offset the segment to where our data is
-
-            "mov ax, 0x1012",
-            "int 0x10",         // Call interrupt
-
-            "pop ax",           // Restore data segment register
-            "mov es, ax",
-
-            in("di") segment,
-            in("dx") offset,
-            in("bx") start,
-            in("cx") colours.len() as u16
-        );
+        far_ptr::interrupt_with_buffer(0x10, BufferLocation::EsDx, FarPtr::from_ptr(colours.as_ptr()), registers);
     }
 }
\ No newline at end of file
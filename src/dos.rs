@@ -1,14 +1,29 @@
+#[cfg(not(test))]
 #[macro_use]
 pub mod console;
 pub mod allocator;
+#[cfg(not(test))]
 pub mod io;
+#[cfg(not(test))]
 pub mod kbc;
 pub mod datetime;
+pub mod env;
 pub mod file;
 pub mod error_code;
+pub mod far_ptr;
+pub mod interrupt;
+#[cfg(not(test))]
 pub mod panic;
 pub mod math;
+pub mod path;
+#[cfg(not(test))]
+pub mod memory;
 pub mod misc;
+#[cfg(test)]
+pub mod mock;
+#[cfg(not(test))]
+pub mod process;
+#[cfg(not(test))]
 use core::arch::asm;
 
 pub use alloc::string::String as String;
@@ -16,6 +31,7 @@ pub use alloc::boxed::Box as Box;
 pub use alloc::vec::Vec as Vec;
 pub use alloc::vec as vec;
 
+#[cfg(not(test))]
 pub fn exit(rt: u8) -> ! {
     unsafe {
         asm!("mov ah, 0x4C",
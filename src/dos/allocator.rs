@@ -1,11 +1,30 @@
 //! Memory heap allocation for DOS programs.
-//! Uses conventional memory for DOS programs, from _heap segment start to extended BIOS data area (EBDA)
+//! Uses conventional memory for DOS programs, from _heap segment start to the top of usable memory
 //! Uses linear algorithm for allocating memory, which is not optimal, but it's simple and works.
+//! Building with the `size-classes` feature keeps free blocks in lists sorted
+//! by size instead, so `alloc` only looks at blocks that can fit the request.
+//!
+//! With the `dos-heap` feature the program's memory block is shrunk at startup
+//! and the heap is made of blocks allocated from DOS instead, asking for more
+//! whenever it runs out. See [crate::dos::memory].
+//!
+//! The block list itself works over any region of memory given to
+//! [DosAllocator::init_region], so it can be tested with `cargo test` on the
+//! host against a simulated heap.
+//!
+//! Every block starts with an [AllocatorBlock] header and runs up to the start
+//! of the next block, so the blocks always cover the whole heap. The pointer
+//! handed out sits right after the header, which lets `dealloc` find the
+//! header again without knowing the alignment that was asked for. It also
+//! lets `realloc` grow into a free block that follows, or shrink by splitting
+//! off the tail, without moving the data.
 
 use core::alloc::{GlobalAlloc, Layout};
+#[cfg(not(test))]
 use core::arch::asm;
+use core::cell::UnsafeCell;
 use core::cmp::min;
-use core::mem::size_of;
+use core::mem::{align_of, size_of};
 
 struct AllocatorBlock {
     next: Option<*mut AllocatorBlock>,
@@ -14,31 +33,148 @@ struct AllocatorBlock {
     used: bool,
 }
 
+/// Hands the heap more memory when it runs out. See
+/// [DosAllocator::set_grow_handler]
+pub type GrowHandler = fn(usize) -> Option<&'static mut [u8]>;
+
 pub struct DosAllocator {
     first_block_ptr: *mut AllocatorBlock,
+    regions: UnsafeCell<Regions>,
+    grow: Option<GrowHandler>,
+    #[cfg(feature = "size-classes")]
+    free_lists: FreeLists,
 }
 
-impl DosAllocator {
-    const LAST_MEMORY_BYTE_ADDR: u32 = 0x9FBFF; // (0X9000 << 4) + 0XFBFF, last byte of memory before extended BIOS data area
-    const ALLOCATOR_BLOCK_SIZE: usize = size_of::<AllocatorBlock>();
-    const MIN_BLOCK_USEFUL_SIZE: usize = 16;
+/// Start and end of the areas of memory the heap is made of, in the order
+/// they were added. The block list runs through them in the same order, but
+/// no block crosses from one region into the next
+struct Regions {
+    bounds: [(usize, usize); DosAllocator::MAX_REGIONS],
+    count: usize,
+}
+
+/// Links kept in the useful part of a free block, chaining it to the other
+/// free blocks of its size class
+#[cfg(feature = "size-classes")]
+struct FreeLink {
+    next: *mut AllocatorBlock,
+    prev: *mut AllocatorBlock,
+}
+
+/// Free blocks sorted by size. Class `n` holds the blocks with at least
+/// `2^(n + 4)` useful bytes, the last class holds everything bigger
+#[cfg(feature = "size-classes")]
+struct FreeLists {
+    heads: UnsafeCell<[*mut AllocatorBlock; FreeLists::SIZE_CLASSES]>,
+}
+
+#[cfg(feature = "size-classes")]
+impl FreeLists {
+    const SIZE_CLASSES: usize = 16;
+
+    const fn new() -> Self {
+        Self {
+            heads: UnsafeCell::new([core::ptr::null_mut(); Self::SIZE_CLASSES]),
+        }
+    }
 
-    fn diff_between_blocks_ptr(first_block: *mut AllocatorBlock, second_block: *mut AllocatorBlock) -> usize {
-        let first_block_addr = first_block as usize;
-        let second_block_addr = second_block as usize;
-        assert!(first_block_addr < second_block_addr);
-        second_block_addr - first_block_addr
+    fn class_of(useful_size: usize) -> usize {
+        let log2 = (usize::BITS - 1 - useful_size.max(1).leading_zeros()) as usize;
+        log2.saturating_sub(4).min(Self::SIZE_CLASSES - 1)
     }
 
-    fn free_space_before_next_block(block: *mut AllocatorBlock) -> usize {
-        assert_ne!(block, core::ptr::null_mut());
-        assert!((block as u32) < Self::LAST_MEMORY_BYTE_ADDR);
-        let next_block = unsafe { (*block).next };
-        if next_block.is_none() {
-            return Self::LAST_MEMORY_BYTE_ADDR as usize - block as usize;
+    fn link(block: *mut AllocatorBlock) -> *mut FreeLink {
+        DosAllocator::block_addr_to_useful_ptr(block) as *mut FreeLink
+    }
+
+    unsafe fn head(&self, class: usize) -> *mut AllocatorBlock {
+        (*self.heads.get())[class]
+    }
+
+    unsafe fn insert(&self, block: *mut AllocatorBlock) {
+        let class = Self::class_of((*block).size - DosAllocator::ALLOCATOR_BLOCK_SIZE);
+        let head = self.head(class);
+
+        *Self::link(block) = FreeLink {
+            next: head,
+            prev: core::ptr::null_mut(),
+        };
+
+        if !head.is_null() {
+            (*Self::link(head)).prev = block;
+        }
+
+        (*self.heads.get())[class] = block;
+    }
+
+    unsafe fn remove(&self, block: *mut AllocatorBlock) {
+        let link = Self::link(block);
+
+        if (*link).prev.is_null() {
+            let class = Self::class_of((*block).size - DosAllocator::ALLOCATOR_BLOCK_SIZE);
+            (*self.heads.get())[class] = (*link).next;
+        } else {
+            (*Self::link((*link).prev)).next = (*link).next;
         }
-        let next_block = next_block.unwrap();
-        Self::diff_between_blocks_ptr(block, next_block) - Self::ALLOCATOR_BLOCK_SIZE
+
+        if !(*link).next.is_null() {
+            (*Self::link((*link).next)).prev = (*link).prev;
+        }
+    }
+}
+
+/// Snapshot of how the heap is being used. See [DosAllocator::stats]
+///
+/// Byte counts include the header in front of every block, so `used` and
+/// `free` always add up to `total`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HeapStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Biggest allocation that could currently succeed, ignoring alignment
+    pub largest_free: usize,
+    pub used_blocks: usize,
+    pub free_blocks: usize,
+}
+
+/// Damage found by [DosAllocator::verify]. Addresses point at the block
+/// header where the problem was noticed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeapCorruption {
+    /// The heap was never set up
+    NotInitialized,
+    /// A block header lies outside of the heap, or is smaller than a header
+    BlockOutOfBounds { block: usize },
+    /// A block's `prev` doesn't point back at the block before it
+    BrokenPrevLink { block: usize },
+    /// A block's `next` doesn't start where the block's size says it ends
+    BrokenNextLink { block: usize },
+    /// Two free blocks next to each other that should have been merged
+    UnmergedFreeBlocks { block: usize },
+    /// The block sizes don't add up to the size of the heap
+    SizeMismatch { expected: usize, found: usize },
+    /// A free list entry is used, in the wrong size class or badly linked,
+    /// or the lists don't hold every free block. Only checked with the
+    /// `size-classes` feature
+    BrokenFreeList { block: usize },
+}
+
+impl DosAllocator {
+    const ALLOCATOR_BLOCK_SIZE: usize = size_of::<AllocatorBlock>();
+    const ALLOCATOR_BLOCK_ALIGN: usize = align_of::<AllocatorBlock>();
+    /// Also the smallest allocation handed out, so any block can hold a
+    /// [FreeLink] once it's freed
+    const MIN_BLOCK_USEFUL_SIZE: usize = 16;
+    /// Smallest block worth splitting off, anything less stays part of its neighbour
+    const MIN_BLOCK_SIZE: usize = Self::ALLOCATOR_BLOCK_SIZE + Self::MIN_BLOCK_USEFUL_SIZE;
+    /// Most separate areas of memory the heap can be spread over
+    const MAX_REGIONS: usize = 32;
+    #[cfg(all(not(test), feature = "dos-heap"))]
+    const HEAP_GROW_PARAGRAPHS: u16 = 0x400; // 16KB
+
+    fn align_up(value: usize, align: usize) -> usize {
+        (value + align - 1) & !(align - 1)
     }
 
     /// Converts block address to pointer usable by the program
@@ -46,43 +182,213 @@ impl DosAllocator {
         assert_ne!(block, core::ptr::null_mut());
         (block as usize + Self::ALLOCATOR_BLOCK_SIZE) as *mut u8
     }
-}
 
-unsafe impl GlobalAlloc for DosAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// Converts a pointer given to the program back to its block
+    fn useful_ptr_to_block_addr(ptr: *mut u8) -> *mut AllocatorBlock {
+        assert_ne!(ptr, core::ptr::null_mut());
+        (ptr as usize - Self::ALLOCATOR_BLOCK_SIZE) as *mut AllocatorBlock
+    }
+
+    /// Cut a block in two at `offset` bytes from its start. The new second
+    /// half is free and returned
+    unsafe fn split_block(block: *mut AllocatorBlock, offset: usize) -> *mut AllocatorBlock {
+        assert!(offset >= Self::ALLOCATOR_BLOCK_SIZE);
+        assert!((*block).size - offset >= Self::ALLOCATOR_BLOCK_SIZE);
+
+        let new_block_ptr = (block as usize + offset) as *mut AllocatorBlock;
+        *new_block_ptr = AllocatorBlock {
+            next: (*block).next,
+            prev: Some(block),
+            size: (*block).size - offset,
+            used: false,
+        };
+
+        if let Some(next_block_ptr) = (*block).next {
+            (*next_block_ptr).prev = Some(new_block_ptr);
+        }
+
+        (*block).next = Some(new_block_ptr);
+        (*block).size = offset;
+
+        new_block_ptr
+    }
+
+    /// Whether `second` starts right where `first` ends. Blocks at the edges
+    /// of two regions never are, so they don't get merged
+    unsafe fn adjacent(first: *mut AllocatorBlock, second: *mut AllocatorBlock) -> bool {
+        first as usize + (*first).size == second as usize
+    }
+
+    /// Fold the block following `block` into it
+    unsafe fn merge_with_next(block: *mut AllocatorBlock) {
+        let next_block_ptr = (*block).next.unwrap();
+
+        if let Some(after_next_ptr) = (*next_block_ptr).next {
+            (*after_next_ptr).prev = Some(block);
+        }
+
+        (*block).size += (*next_block_ptr).size;
+        (*block).next = (*next_block_ptr).next;
+    }
+
+    /// Try to place `size` bytes aligned to `align` in a free block. Space
+    /// left over in front or behind is split off as free blocks when it is
+    /// big enough, otherwise it stays in the allocated block
+    unsafe fn allocate_in_block(&self, block: *mut AllocatorBlock, size: usize, align: usize) -> Option<*mut u8> {
+        let block_addr = block as usize;
+        let block_end = block_addr + (*block).size;
+
+        let mut useful_addr = Self::align_up(block_addr + Self::ALLOCATOR_BLOCK_SIZE, align);
+        if useful_addr - Self::ALLOCATOR_BLOCK_SIZE != block_addr {
+            // The gap before the header has to be able to stand as a free
+            // block of its own
+            useful_addr = Self::align_up(block_addr + Self::MIN_BLOCK_SIZE + Self::ALLOCATOR_BLOCK_SIZE, align);
+        }
+
+        if useful_addr + size > block_end {
+            return None;
+        }
+
+        self.claim_block(block);
+
+        let gap = useful_addr - Self::ALLOCATOR_BLOCK_SIZE - block_addr;
+        let used_block_ptr = if gap > 0 {
+            let used_block_ptr = Self::split_block(block, gap);
+            (*used_block_ptr).used = true;
+            self.release_block(block);
+            used_block_ptr
+        } else {
+            block
+        };
+
+        let used_size = useful_addr + size - used_block_ptr as usize;
+        if (*used_block_ptr).size - used_size >= Self::MIN_BLOCK_SIZE {
+            let free_block_ptr = Self::split_block(used_block_ptr, used_size);
+            self.release_block(free_block_ptr);
+        }
+
+        Some(Self::block_addr_to_useful_ptr(used_block_ptr))
+    }
+
+    /// Mark a block as free and make it available to `alloc`
+    unsafe fn release_block(&self, block: *mut AllocatorBlock) {
+        (*block).used = false;
+
+        #[cfg(feature = "size-classes")]
+        self.free_lists.insert(block);
+    }
+
+    /// Take a free block out of circulation, before it gets used or merged
+    /// into another block
+    unsafe fn claim_block(&self, block: *mut AllocatorBlock) {
+        #[cfg(feature = "size-classes")]
+        self.free_lists.remove(block);
+
+        (*block).used = true;
+    }
+
+    /// Find room for `size` bytes aligned to `align`, walking every block
+    #[cfg(not(feature = "size-classes"))]
+    unsafe fn find_and_allocate(&self, size: usize, align: usize) -> *mut u8 {
         // Look for next free block
         let mut current_block_ptr = self.first_block_ptr;
-        while (*current_block_ptr).used || (*current_block_ptr).size < layout.size() + Self::ALLOCATOR_BLOCK_SIZE {
+        loop {
+            if !(*current_block_ptr).used {
+                if let Some(ptr) = self.allocate_in_block(current_block_ptr, size, align) {
+                    return ptr;
+                }
+            }
+
             current_block_ptr = match (*current_block_ptr).next {
                 Some(ptr) => ptr,
                 None => return core::ptr::null_mut(), // No free block found, return null ptr
             };
         }
+    }
+
+    /// Find room for `size` bytes aligned to `align`, starting with the
+    /// smallest size class that could hold it. Blocks in the bigger classes
+    /// are always large enough, unless alignment gets in the way
+    #[cfg(feature = "size-classes")]
+    unsafe fn find_and_allocate(&self, size: usize, align: usize) -> *mut u8 {
+        for class in FreeLists::class_of(size)..FreeLists::SIZE_CLASSES {
+            let mut current_block_ptr = self.free_lists.head(class);
+
+            while !current_block_ptr.is_null() {
+                // Read the link first, it's overwritten once the block is used
+                let next_block_ptr = (*FreeLists::link(current_block_ptr)).next;
+
+                if let Some(ptr) = self.allocate_in_block(current_block_ptr, size, align) {
+                    return ptr;
+                }
 
-        let free_space_before_next_block = Self::free_space_before_next_block(current_block_ptr);
-        if free_space_before_next_block <= Self::MIN_BLOCK_USEFUL_SIZE + Self::ALLOCATOR_BLOCK_SIZE {
-            // No space for new block, just use the whole space
-            (*current_block_ptr).used = true;
-            (*current_block_ptr).size = free_space_before_next_block;
-            return Self::block_addr_to_useful_ptr(current_block_ptr);
+                current_block_ptr = next_block_ptr;
+            }
         }
 
-        // Create a new unused block between current and next
+        core::ptr::null_mut()
+    }
+
+    /// Try to make a used block hold `size` bytes without moving it, taking
+    /// space from the next block if it is free. Space no longer needed is
+    /// split off as a free block when it is big enough
+    unsafe fn resize_in_place(&self, block: *mut AllocatorBlock, size: usize) -> bool {
+        let used_size = Self::ALLOCATOR_BLOCK_SIZE + size;
 
-        let current_block_size = layout.size() + Self::ALLOCATOR_BLOCK_SIZE;
+        if (*block).size < used_size {
+            let next_block_ptr = match (*block).next {
+                Some(ptr) if !(*ptr).used && Self::adjacent(block, ptr) => ptr,
+                _ => return false,
+            };
 
-        // Create a new block just after current block
-        let new_block_ptr = (current_block_size + current_block_ptr as usize) as *mut AllocatorBlock;
-        (*new_block_ptr).next = (*current_block_ptr).next;
-        (*new_block_ptr).prev = Some(current_block_ptr);
-        (*new_block_ptr).size = free_space_before_next_block - current_block_size;
-        (*new_block_ptr).used = false;
+            if (*block).size + (*next_block_ptr).size < used_size {
+                return false;
+            }
 
-        (*current_block_ptr).next = Some(new_block_ptr);
-        (*current_block_ptr).size = current_block_size;
-        (*current_block_ptr).used = true;
+            self.claim_block(next_block_ptr);
+            Self::merge_with_next(block);
+        }
 
-        Self::block_addr_to_useful_ptr(current_block_ptr)
+        if (*block).size - used_size >= Self::MIN_BLOCK_SIZE {
+            let free_block_ptr = Self::split_block(block, used_size);
+
+            // Shrinking can leave the new tail next to a free block
+            if let Some(next_block_ptr) = (*free_block_ptr).next {
+                if !(*next_block_ptr).used && Self::adjacent(free_block_ptr, next_block_ptr) {
+                    self.claim_block(next_block_ptr);
+                    Self::merge_with_next(free_block_ptr);
+                }
+            }
+
+            self.release_block(free_block_ptr);
+        }
+
+        true
+    }
+}
+
+unsafe impl GlobalAlloc for DosAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Keep every block header aligned by only handing out whole multiples
+        // of its alignment
+        let size = Self::align_up(layout.size().max(Self::MIN_BLOCK_USEFUL_SIZE), Self::ALLOCATOR_BLOCK_ALIGN);
+        let align = layout.align().max(Self::ALLOCATOR_BLOCK_ALIGN);
+
+        let ptr = self.find_and_allocate(size, align);
+        if !ptr.is_null() {
+            return ptr;
+        }
+
+        // Enough for the block, a free block split off in front to align it,
+        // and trimming the ends of the region to the header alignment
+        let needed = size + align + Self::MIN_BLOCK_SIZE + Self::ALLOCATOR_BLOCK_SIZE + 2 * Self::ALLOCATOR_BLOCK_ALIGN;
+        if let Some(region) = self.grow.and_then(|grow| grow(needed)) {
+            if self.add_region(region) {
+                return self.find_and_allocate(size, align);
+            }
+        }
+
+        core::ptr::null_mut()
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
@@ -91,44 +397,40 @@ unsafe impl GlobalAlloc for DosAllocator {
             return;
         }
 
-        let current_block_ptr = (ptr as u32 - Self::ALLOCATOR_BLOCK_SIZE as u32) as *mut AllocatorBlock;
-        // Mark block as free
-        (*current_block_ptr).used = false;
+        let mut current_block_ptr = Self::useful_ptr_to_block_addr(ptr);
 
         // Merge with next block if it's free
-        let next_block_ptr = (*current_block_ptr).next;
-        if next_block_ptr.is_some() {
-            let next_block_ptr = next_block_ptr.unwrap();
-            if !(*next_block_ptr).used {
-                if (*next_block_ptr).next.is_some() {
-                    (*(*next_block_ptr).next.unwrap()).prev = Some(current_block_ptr);
-                }
-                (*current_block_ptr).size += (*next_block_ptr).size;
-                (*current_block_ptr).next = (*next_block_ptr).next;
+        if let Some(next_block_ptr) = (*current_block_ptr).next {
+            if !(*next_block_ptr).used && Self::adjacent(current_block_ptr, next_block_ptr) {
+                self.claim_block(next_block_ptr);
+                Self::merge_with_next(current_block_ptr);
             }
         }
 
         // Merge with previous block if it's free
-        let prev_block_ptr = (*current_block_ptr).prev;
-        if prev_block_ptr.is_some() {
-            let prev_block_ptr = prev_block_ptr.unwrap();
-            if !(*prev_block_ptr).used {
-                if (*current_block_ptr).next.is_some() {
-                    (*(*current_block_ptr).next.unwrap()).prev = Some(prev_block_ptr);
-                }
-                (*prev_block_ptr).size += (*current_block_ptr).size;
-                (*prev_block_ptr).next = (*current_block_ptr).next;
+        if let Some(prev_block_ptr) = (*current_block_ptr).prev {
+            if !(*prev_block_ptr).used && Self::adjacent(prev_block_ptr, current_block_ptr) {
+                self.claim_block(prev_block_ptr);
+                Self::merge_with_next(prev_block_ptr);
+                current_block_ptr = prev_block_ptr;
             }
         }
+
+        // Mark block as free
+        self.release_block(current_block_ptr);
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
         assert_ne!(ptr, core::ptr::null_mut()); // Avoid undefined behavior
-        let current_block_ptr = (ptr as u32 - Self::ALLOCATOR_BLOCK_SIZE as u32) as *mut AllocatorBlock;
-        if (*current_block_ptr).size >= new_size + Self::ALLOCATOR_BLOCK_SIZE {
+        let current_block_ptr = Self::useful_ptr_to_block_addr(ptr);
+        let size = Self::align_up(new_size.max(Self::MIN_BLOCK_USEFUL_SIZE), Self::ALLOCATOR_BLOCK_ALIGN);
+        if self.resize_in_place(current_block_ptr, size) {
             return ptr;
         }
         let new_ptr = self.alloc(Layout::from_size_align(new_size, layout.align()).unwrap());
+        if new_ptr.is_null() {
+            return new_ptr;
+        }
         new_ptr.copy_from_nonoverlapping(ptr, min(layout.size(), new_size));
         self.dealloc(ptr, layout);
         new_ptr
@@ -136,6 +438,9 @@ unsafe impl GlobalAlloc for DosAllocator {
 }
 
 impl DosAllocator {
+    /// Set up the heap over conventional memory, from the `_heap` symbol up to
+    /// the end of usable memory found by [super::memory::memory_limits]
+    #[cfg(all(not(test), not(feature = "dos-heap")))]
     #[allow(unused_assignments)]
     pub fn init(&mut self) {
         let mut heap_segment_number: u32 = 0;
@@ -144,34 +449,929 @@ impl DosAllocator {
         }
 
         // Compute heap address from segment number
-        let heap_addr = ((heap_segment_number & 0xFFFF) << 4) as u32;
-        let heap_ptr_as_block = heap_addr as *mut AllocatorBlock;
+        let heap_addr = ((heap_segment_number & 0xFFFF) << 4) as usize;
+        let heap_end = super::memory::memory_limits().top_offset();
+        assert!(heap_addr < heap_end);
+
+        unsafe {
+            self.init_region(core::slice::from_raw_parts_mut(heap_addr as *mut u8, heap_end - heap_addr));
+        }
+    }
+
+    /// Give our memory block back to DOS, apart from the program itself, and
+    /// start the heap off with a block allocated from DOS. More are requested
+    /// as the heap fills up
+    #[cfg(all(not(test), feature = "dos-heap"))]
+    pub fn init(&mut self) {
+        super::memory::shrink_program_block().expect("couldn't shrink the program's memory block");
+
+        let region = Self::grow_from_dos(Self::HEAP_GROW_PARAGRAPHS as usize * super::memory::PARAGRAPH_SIZE)
+            .expect("no memory left for the heap");
+
+        unsafe {
+            self.init_region(region);
+        }
+
+        self.set_grow_handler(Self::grow_from_dos);
+    }
+
+    /// Allocate a DOS memory block of at least `size` bytes for the heap.
+    /// The block belongs to the heap until the program exits
+    #[cfg(all(not(test), feature = "dos-heap"))]
+    fn grow_from_dos(size: usize) -> Option<&'static mut [u8]> {
+        use super::memory::{self, MemoryBlock};
+
+        let needed = (size + memory::PARAGRAPH_SIZE - 1) / memory::PARAGRAPH_SIZE;
+        let needed = u16::try_from(needed).ok()?;
+
+        let mut block = MemoryBlock::allocate(needed.max(Self::HEAP_GROW_PARAGRAPHS))
+            .or_else(|_| MemoryBlock::allocate(needed))
+            .ok()?;
+
+        let region = unsafe { block.as_mut_slice()? as *mut [u8] };
+        block.into_segment();
+
+        unsafe { Some(&mut *region) }
+    }
+
+    /// Set up the heap over any region of memory. All allocations are served
+    /// from it, which is how the allocator is exercised on the host.
+    ///
+    /// # Safety
+    ///
+    /// The region isn't borrowed past this call, so it's up to the caller to
+    /// keep it alive and untouched for as long as the allocator is used
+    pub unsafe fn init_region(&mut self, region: &mut [u8]) {
+        let (heap_addr, heap_end) = Self::region_bounds(region);
+        assert!(heap_addr + Self::MIN_BLOCK_SIZE <= heap_end);
 
         // Create an empty block at the beginning of the heap, containing all free space
-        assert!(heap_addr as u32 <= DosAllocator::LAST_MEMORY_BYTE_ADDR);
-        let first_block_size = DosAllocator::LAST_MEMORY_BYTE_ADDR - heap_addr;
+        let heap_ptr_as_block = heap_addr as *mut AllocatorBlock;
+        *heap_ptr_as_block = AllocatorBlock {
+            next: None,
+            prev: None,
+            size: heap_end - heap_addr,
+            used: false,
+        };
+        self.first_block_ptr = heap_ptr_as_block;
+
+        let regions = self.regions.get_mut();
+        regions.bounds[0] = (heap_addr, heap_end);
+        regions.count = 1;
+
+        #[cfg(feature = "size-classes")]
+        {
+            self.free_lists = FreeLists::new();
+            self.free_lists.insert(heap_ptr_as_block);
+        }
+    }
+
+    /// Add another region of memory to an initialized heap. Returns false if
+    /// the region is too small, or the heap is already spread over as many
+    /// regions as it can track.
+    ///
+    /// # Safety
+    ///
+    /// Same as [DosAllocator::init_region]. The region mustn't overlap the
+    /// heap
+    pub unsafe fn add_region(&self, region: &mut [u8]) -> bool {
+        assert!(!self.first_block_ptr.is_null());
+
+        let (region_start, region_end) = Self::region_bounds(region);
+        if region_start + Self::MIN_BLOCK_SIZE > region_end {
+            return false;
+        }
+
+        let regions = &mut *self.regions.get();
+        let last_region = regions.count - 1;
+
+        let mut last_block_ptr = regions.bounds[last_region].0 as *mut AllocatorBlock;
+        while let Some(next_block_ptr) = (*last_block_ptr).next {
+            last_block_ptr = next_block_ptr;
+        }
+
+        if region_start == regions.bounds[last_region].1 {
+            // Memory right after the heap, just make the last region bigger
+            regions.bounds[last_region].1 = region_end;
+
+            if !(*last_block_ptr).used {
+                self.claim_block(last_block_ptr);
+                (*last_block_ptr).size += region_end - region_start;
+                self.release_block(last_block_ptr);
+                return true;
+            }
+        } else {
+            if regions.count == Self::MAX_REGIONS {
+                return false;
+            }
+
+            regions.bounds[regions.count] = (region_start, region_end);
+            regions.count += 1;
+        }
+
+        let new_block_ptr = region_start as *mut AllocatorBlock;
+        *new_block_ptr = AllocatorBlock {
+            next: None,
+            prev: Some(last_block_ptr),
+            size: region_end - region_start,
+            used: false,
+        };
+        (*last_block_ptr).next = Some(new_block_ptr);
+        self.release_block(new_block_ptr);
+
+        true
+    }
+
+    /// Called with the number of bytes needed when no free block is big
+    /// enough for an allocation. Any memory it hands back is added to the
+    /// heap with [DosAllocator::add_region] before trying again
+    pub fn set_grow_handler(&mut self, grow: GrowHandler) {
+        self.grow = Some(grow);
+    }
+
+    /// Part of a region that blocks can be placed in, with both ends aligned
+    /// for block headers
+    fn region_bounds(region: &mut [u8]) -> (usize, usize) {
+        let region_start = region.as_mut_ptr() as usize;
+        let start = Self::align_up(region_start, Self::ALLOCATOR_BLOCK_ALIGN);
+        let end = (region_start + region.len()) & !(Self::ALLOCATOR_BLOCK_ALIGN - 1);
+
+        (start, end.max(start))
+    }
+
+    /// Whether `size` bytes at `addr` lie inside one of the heap's regions
+    #[cfg(feature = "size-classes")]
+    fn in_heap(&self, addr: usize, size: usize) -> bool {
+        let regions = unsafe { &*self.regions.get() };
+
+        regions.bounds[..regions.count]
+            .iter()
+            .any(|&(start, end)| addr >= start && addr <= end && size <= end - addr)
+    }
+
+    /// Walk the heap and report how much of it is used
+    pub fn stats(&self) -> HeapStats {
+        let mut stats = HeapStats::default();
+        let mut current_block_ptr = self.first_block_ptr;
+
+        if current_block_ptr.is_null() {
+            return stats;
+        }
+
         unsafe {
-            *heap_ptr_as_block = AllocatorBlock {
-                next: None,
-                prev: None,
-                size: first_block_size as usize,
-                used: false,
-            };
+            loop {
+                let size = (*current_block_ptr).size;
+                stats.total += size;
+
+                if (*current_block_ptr).used {
+                    stats.used += size;
+                    stats.used_blocks += 1;
+                } else {
+                    stats.free += size;
+                    stats.free_blocks += 1;
+                    stats.largest_free = stats.largest_free.max(size - Self::ALLOCATOR_BLOCK_SIZE);
+                }
+
+                current_block_ptr = match (*current_block_ptr).next {
+                    Some(ptr) => ptr,
+                    None => break,
+                };
+            }
         }
-        self.first_block_ptr = heap_ptr_as_block
+
+        stats
     }
 
-    const fn new() -> Self {
+    /// Check the block list for corruption: every header inside the heap,
+    /// `prev` and `next` agreeing with each other and with the block sizes,
+    /// no free blocks left unmerged, and the sizes adding up to the heap.
+    ///
+    /// Stops at the first problem, since anything after a broken header
+    /// can't be trusted
+    pub fn verify(&self) -> Result<(), HeapCorruption> {
+        if self.first_block_ptr.is_null() {
+            return Err(HeapCorruption::NotInitialized);
+        }
+
+        let regions = unsafe { &*self.regions.get() };
+        let mut region = 0;
+        let mut current_block_ptr = self.first_block_ptr;
+        let mut prev_block_ptr: Option<*mut AllocatorBlock> = None;
+        let mut total = 0;
+
+        unsafe {
+            loop {
+                let (region_start, region_end) = regions.bounds[region];
+                let block_addr = current_block_ptr as usize;
+                if block_addr < region_start || block_addr + Self::ALLOCATOR_BLOCK_SIZE > region_end {
+                    return Err(HeapCorruption::BlockOutOfBounds { block: block_addr });
+                }
+
+                let size = (*current_block_ptr).size;
+                if size < Self::ALLOCATOR_BLOCK_SIZE || size > region_end - block_addr {
+                    return Err(HeapCorruption::BlockOutOfBounds { block: block_addr });
+                }
+
+                if (*current_block_ptr).prev != prev_block_ptr {
+                    return Err(HeapCorruption::BrokenPrevLink { block: block_addr });
+                }
+
+                total += size;
+
+                let next_block_ptr = match (*current_block_ptr).next {
+                    Some(ptr) => ptr,
+                    None => break,
+                };
+
+                if block_addr + size == region_end {
+                    // The last block in a region leads on to the next region
+                    region += 1;
+                    if region == regions.count || next_block_ptr as usize != regions.bounds[region].0 {
+                        return Err(HeapCorruption::BrokenNextLink { block: block_addr });
+                    }
+                } else {
+                    // Blocks are laid out back to back, which also rules out loops
+                    if next_block_ptr as usize != block_addr + size {
+                        return Err(HeapCorruption::BrokenNextLink { block: block_addr });
+                    }
+
+                    if !(*current_block_ptr).used && !(*next_block_ptr).used {
+                        return Err(HeapCorruption::UnmergedFreeBlocks { block: block_addr });
+                    }
+                }
+
+                prev_block_ptr = Some(current_block_ptr);
+                current_block_ptr = next_block_ptr;
+            }
+        }
+
+        let expected = regions.bounds[..regions.count].iter().map(|(start, end)| end - start).sum();
+        if total != expected {
+            return Err(HeapCorruption::SizeMismatch { expected, found: total });
+        }
+
+        #[cfg(feature = "size-classes")]
+        self.verify_free_lists()?;
+
+        Ok(())
+    }
+
+    /// Check every free list entry is a free block of the right size class,
+    /// linked both ways, and that the lists hold every free block. Only
+    /// called once the block list itself is known to be sound
+    #[cfg(feature = "size-classes")]
+    fn verify_free_lists(&self) -> Result<(), HeapCorruption> {
+        let free_blocks = self.stats().free_blocks;
+        let mut found = 0;
+
+        unsafe {
+            for class in 0..FreeLists::SIZE_CLASSES {
+                let mut current_block_ptr = self.free_lists.head(class);
+                let mut prev_block_ptr = core::ptr::null_mut();
+
+                while !current_block_ptr.is_null() {
+                    let block_addr = current_block_ptr as usize;
+                    if !self.in_heap(block_addr, Self::MIN_BLOCK_SIZE) || found == free_blocks {
+                        return Err(HeapCorruption::BrokenFreeList { block: block_addr });
+                    }
+
+                    let link = FreeLists::link(current_block_ptr);
+                    if (*current_block_ptr).used
+                        || FreeLists::class_of((*current_block_ptr).size - Self::ALLOCATOR_BLOCK_SIZE) != class
+                        || (*link).prev != prev_block_ptr {
+                        return Err(HeapCorruption::BrokenFreeList { block: block_addr });
+                    }
+
+                    found += 1;
+                    prev_block_ptr = current_block_ptr;
+                    current_block_ptr = (*link).next;
+                }
+            }
+        }
+
+        if found != free_blocks {
+            return Err(HeapCorruption::BrokenFreeList { block: 0 });
+        }
+
+        Ok(())
+    }
+
+    /// Address just past the last block in use. Everything from there to the
+    /// end of conventional memory is a single free block, apart from its
+    /// header
+    #[cfg_attr(feature = "dos-heap", allow(dead_code))]
+    pub(crate) fn used_end(&self) -> usize {
+        let mut current_block_ptr = self.first_block_ptr;
+
+        unsafe {
+            while let Some(next_block_ptr) = (*current_block_ptr).next {
+                current_block_ptr = next_block_ptr;
+            }
+
+            if (*current_block_ptr).used {
+                current_block_ptr as usize + (*current_block_ptr).size
+            } else {
+                current_block_ptr as usize + Self::ALLOCATOR_BLOCK_SIZE
+            }
+        }
+    }
+
+    pub const fn new() -> Self {
         Self {
             first_block_ptr: core::ptr::null_mut(),
+            regions: UnsafeCell::new(Regions {
+                bounds: [(0, 0); Self::MAX_REGIONS],
+                count: 0,
+            }),
+            grow: None,
+            #[cfg(feature = "size-classes")]
+            free_lists: FreeLists::new(),
         }
     }
 }
 
+impl Default for DosAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Usage statistics for the program's heap
+#[cfg(not(test))]
+pub fn heap_stats() -> HeapStats {
+    unsafe { GLOBAL_ALLOCATOR.stats() }
+}
+
+/// Check the program's heap for corruption. See [DosAllocator::verify]
+#[cfg(not(test))]
+pub fn verify_heap() -> Result<(), HeapCorruption> {
+    unsafe { GLOBAL_ALLOCATOR.verify() }
+}
+
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error_handler(layout: Layout) -> ! {
     panic!("allocation error: {:?}", layout);
 }
 
+#[cfg(not(test))]
 #[global_allocator]
-pub(crate) static mut GLOBAL_ALLOCATOR: DosAllocator = DosAllocator::new();
\ No newline at end of file
+pub(crate) static mut GLOBAL_ALLOCATOR: DosAllocator = DosAllocator::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    const HEAP_SIZE: usize = 64 * 1024;
+
+    /// Heap region for a test. Kept as u64s so the start is predictable, the
+    /// allocator is handed an unaligned slice of it on purpose
+    struct TestHeap {
+        memory: Vec<u64>,
+        allocator: DosAllocator,
+    }
+
+    impl TestHeap {
+        fn new(offset: usize) -> Self {
+            let mut memory = std::vec![0u64; HEAP_SIZE / 8];
+            let mut allocator = DosAllocator::new();
+
+            unsafe {
+                let bytes = core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, HEAP_SIZE);
+                allocator.init_region(&mut bytes[offset..]);
+            }
+
+            Self {
+                memory,
+                allocator,
+            }
+        }
+
+        fn range(&self) -> (usize, usize) {
+            let start = self.memory.as_ptr() as usize;
+            (start, start + HEAP_SIZE)
+        }
+
+        /// Walk the block list and check it still tiles the heap
+        fn check_blocks(&self) -> Vec<(usize, usize, bool)> {
+            assert_eq!(self.allocator.verify(), Ok(()));
+
+            let mut blocks = Vec::new();
+            let mut current_block_ptr = self.allocator.first_block_ptr;
+            let mut prev_block_ptr = None;
+
+            unsafe {
+                loop {
+                    assert_eq!((*current_block_ptr).prev, prev_block_ptr);
+                    assert!((*current_block_ptr).size >= DosAllocator::ALLOCATOR_BLOCK_SIZE);
+                    blocks.push((current_block_ptr as usize, (*current_block_ptr).size, (*current_block_ptr).used));
+
+                    match (*current_block_ptr).next {
+                        Some(next_block_ptr) => {
+                            assert_eq!(current_block_ptr as usize + (*current_block_ptr).size, next_block_ptr as usize);
+                            assert!((*current_block_ptr).used || (*next_block_ptr).used, "free blocks left unmerged");
+                            prev_block_ptr = Some(current_block_ptr);
+                            current_block_ptr = next_block_ptr;
+                        },
+                        None => break,
+                    }
+                }
+            }
+
+            let (start, end) = self.range();
+            let (last_addr, last_size, _) = *blocks.last().unwrap();
+            assert!(blocks[0].0 >= start);
+            assert!(last_addr + last_size <= end);
+
+            blocks
+        }
+    }
+
+    /// Small xorshift generator so the fuzz runs are repeatable
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, limit: usize) -> usize {
+            (self.next() % limit as u64) as usize
+        }
+    }
+
+    /// What the reference model remembers about a live allocation
+    struct Allocation {
+        ptr: *mut u8,
+        layout: Layout,
+        fill: u8,
+    }
+
+    impl Allocation {
+        fn check_contents(&self) {
+            for offset in 0..self.layout.size() {
+                assert_eq!(unsafe { *self.ptr.add(offset) }, self.fill, "allocation at {:p} was overwritten", self.ptr);
+            }
+        }
+
+        fn overlaps(&self, other: &Allocation) -> bool {
+            let start = self.ptr as usize;
+            let other_start = other.ptr as usize;
+            start < other_start + other.layout.size().max(1) && other_start < start + self.layout.size().max(1)
+        }
+    }
+
+    fn check_new_allocation(heap: &TestHeap, live: &[Allocation], allocation: &Allocation) {
+        let (start, end) = heap.range();
+        let address = allocation.ptr as usize;
+
+        assert_eq!(address % allocation.layout.align(), 0);
+        assert!(address >= start && address + allocation.layout.size() <= end);
+        assert!(live.iter().all(|other| !allocation.overlaps(other)));
+    }
+
+    #[test]
+    fn allocate_and_free_everything() {
+        let heap = TestHeap::new(0);
+        let (_, free_size, used) = heap.check_blocks()[0];
+        assert!(!used);
+
+        let layout = Layout::from_size_align(100, 4).unwrap();
+        let pointers: Vec<*mut u8> = (0..10).map(|_| unsafe { heap.allocator.alloc(layout) }).collect();
+        assert!(pointers.iter().all(|ptr| !ptr.is_null()));
+        assert_eq!(heap.check_blocks().len(), 11);
+
+        for ptr in pointers {
+            unsafe { heap.allocator.dealloc(ptr, layout) };
+        }
+
+        let blocks = heap.check_blocks();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1, free_size);
+        assert!(!blocks[0].2);
+    }
+
+    #[test]
+    fn out_of_memory_returns_null() {
+        let heap = TestHeap::new(0);
+        let layout = Layout::from_size_align(HEAP_SIZE, 1).unwrap();
+
+        assert!(unsafe { heap.allocator.alloc(layout) }.is_null());
+    }
+
+    #[test]
+    fn mixed_alignments() {
+        for offset in [0, 1, 3, 6] {
+            let heap = TestHeap::new(offset);
+            let mut live: Vec<Allocation> = Vec::new();
+
+            for i in 0..200 {
+                let layout = Layout::from_size_align(1 + (i * 13) % 90, 1 << (i % 10)).unwrap();
+                let ptr = unsafe { heap.allocator.alloc(layout) };
+                assert!(!ptr.is_null());
+
+                let allocation = Allocation { ptr, layout, fill: i as u8 };
+                check_new_allocation(&heap, &live, &allocation);
+                unsafe { ptr.write_bytes(allocation.fill, layout.size()) };
+                live.push(allocation);
+            }
+
+            heap.check_blocks();
+
+            for allocation in live.drain(..) {
+                allocation.check_contents();
+                unsafe { heap.allocator.dealloc(allocation.ptr, allocation.layout) };
+            }
+
+            assert_eq!(heap.check_blocks().len(), 1);
+        }
+    }
+
+    #[test]
+    fn fuzz_against_reference_model() {
+        for seed in 1..=8u64 {
+            let heap = TestHeap::new(seed as usize % 4);
+            let mut random = Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut live: Vec<Allocation> = Vec::new();
+
+            for step in 0..5000 {
+                match random.below(10) {
+                    0..=4 => {
+                        let layout = Layout::from_size_align(random.below(700), 1 << random.below(8)).unwrap();
+                        let ptr = unsafe { heap.allocator.alloc(layout) };
+                        if ptr.is_null() {
+                            continue;
+                        }
+
+                        let allocation = Allocation { ptr, layout, fill: step as u8 };
+                        check_new_allocation(&heap, &live, &allocation);
+                        unsafe { ptr.write_bytes(allocation.fill, layout.size()) };
+                        live.push(allocation);
+                    },
+                    5..=7 if !live.is_empty() => {
+                        let allocation = live.swap_remove(random.below(live.len()));
+                        allocation.check_contents();
+                        unsafe { heap.allocator.dealloc(allocation.ptr, allocation.layout) };
+                    },
+                    8..=9 if !live.is_empty() => {
+                        let old = live.swap_remove(random.below(live.len()));
+                        let new_size = random.below(1200);
+                        let ptr = unsafe { heap.allocator.realloc(old.ptr, old.layout, new_size) };
+                        if ptr.is_null() {
+                            old.check_contents();
+                            live.push(old);
+                            continue;
+                        }
+
+                        let layout = Layout::from_size_align(new_size, old.layout.align()).unwrap();
+                        let allocation = Allocation { ptr, layout, fill: step as u8 };
+                        check_new_allocation(&heap, &live, &allocation);
+                        for offset in 0..old.layout.size().min(new_size) {
+                            assert_eq!(unsafe { *ptr.add(offset) }, old.fill, "realloc lost data");
+                        }
+                        unsafe { ptr.write_bytes(allocation.fill, new_size) };
+                        live.push(allocation);
+                    },
+                    _ => {},
+                }
+
+                if step % 100 == 0 {
+                    heap.check_blocks();
+                }
+            }
+
+            for allocation in live.drain(..) {
+                allocation.check_contents();
+                unsafe { heap.allocator.dealloc(allocation.ptr, allocation.layout) };
+            }
+
+            assert_eq!(heap.check_blocks().len(), 1);
+        }
+    }
+
+    #[test]
+    fn used_end_tracks_last_block() {
+        let heap = TestHeap::new(0);
+        let first_block_addr = heap.check_blocks()[0].0;
+        assert_eq!(heap.allocator.used_end(), first_block_addr + DosAllocator::ALLOCATOR_BLOCK_SIZE);
+
+        let layout = Layout::from_size_align(256, 4).unwrap();
+        let ptr = unsafe { heap.allocator.alloc(layout) };
+        assert_eq!(heap.allocator.used_end(), ptr as usize + 256 + DosAllocator::ALLOCATOR_BLOCK_SIZE);
+
+        unsafe { heap.allocator.dealloc(ptr, layout) };
+        assert_eq!(heap.allocator.used_end(), first_block_addr + DosAllocator::ALLOCATOR_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn realloc_grows_in_place() {
+        let heap = TestHeap::new(0);
+        let layout = Layout::from_size_align(64, 4).unwrap();
+        let first = unsafe { heap.allocator.alloc(layout) };
+        let second = unsafe { heap.allocator.alloc(layout) };
+        let third = unsafe { heap.allocator.alloc(layout) };
+        unsafe {
+            second.write_bytes(0xAB, 64);
+            heap.allocator.dealloc(third, layout);
+        }
+
+        // Into the free space at the end of the heap
+        let grown = unsafe { heap.allocator.realloc(second, layout, 1000) };
+        assert_eq!(grown, second);
+        assert!((0..64).all(|offset| unsafe { *grown.add(offset) } == 0xAB));
+        assert_eq!(heap.check_blocks().len(), 3);
+
+        // Absorbing a whole free block that's just big enough
+        let bigger = Layout::from_size_align(1000, 4).unwrap();
+        let blocker = unsafe { heap.allocator.alloc(layout) };
+        unsafe { heap.allocator.dealloc(grown, bigger) };
+        let moved = unsafe { heap.allocator.alloc(layout) };
+        assert_eq!(moved, second);
+        let regrown = unsafe { heap.allocator.realloc(moved, layout, 1000) };
+        assert_eq!(regrown, moved);
+
+        let blocks = heap.check_blocks();
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[..3].iter().all(|&(_, _, used)| used));
+        assert_eq!(blocks[1].0 + blocks[1].1, blocker as usize - DosAllocator::ALLOCATOR_BLOCK_SIZE);
+
+        unsafe {
+            heap.allocator.dealloc(first, layout);
+            heap.allocator.dealloc(regrown, bigger);
+            heap.allocator.dealloc(blocker, layout);
+        }
+        assert_eq!(heap.check_blocks().len(), 1);
+    }
+
+    #[test]
+    fn realloc_shrinks_in_place() {
+        let heap = TestHeap::new(0);
+        let large = Layout::from_size_align(2000, 4).unwrap();
+        let small = Layout::from_size_align(40, 4).unwrap();
+        let first = unsafe { heap.allocator.alloc(large) };
+        let second = unsafe { heap.allocator.alloc(small) };
+        unsafe { first.write_bytes(0xCD, 2000) };
+
+        let shrunk = unsafe { heap.allocator.realloc(first, large, 96) };
+        assert_eq!(shrunk, first);
+        assert!((0..96).all(|offset| unsafe { *shrunk.add(offset) } == 0xCD));
+
+        let blocks = heap.check_blocks();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].1, 96 + DosAllocator::ALLOCATOR_BLOCK_SIZE);
+        assert!(!blocks[1].2);
+
+        // The tail freed by shrinking again merges with the free block after it
+        unsafe { heap.allocator.dealloc(second, small) };
+        let shrunk_again = unsafe { heap.allocator.realloc(shrunk, Layout::from_size_align(96, 4).unwrap(), 24) };
+        assert_eq!(shrunk_again, first);
+
+        let blocks = heap.check_blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].1, 24 + DosAllocator::ALLOCATOR_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn realloc_moves_when_blocked() {
+        let heap = TestHeap::new(0);
+        let layout = Layout::from_size_align(64, 4).unwrap();
+        let first = unsafe { heap.allocator.alloc(layout) };
+        let second = unsafe { heap.allocator.alloc(layout) };
+        unsafe { first.write_bytes(0x5A, 64) };
+
+        let moved = unsafe { heap.allocator.realloc(first, layout, 500) };
+        assert_ne!(moved, first);
+        assert!((0..64).all(|offset| unsafe { *moved.add(offset) } == 0x5A));
+
+        let blocks = heap.check_blocks();
+        assert!(!blocks[0].2);
+        assert_eq!(blocks[1].0, second as usize - DosAllocator::ALLOCATOR_BLOCK_SIZE);
+
+        // Nothing big enough anywhere leaves the old allocation alone
+        assert!(unsafe { heap.allocator.realloc(second, layout, HEAP_SIZE) }.is_null());
+        assert_eq!(heap.check_blocks()[1].0, second as usize - DosAllocator::ALLOCATOR_BLOCK_SIZE);
+    }
+
+    /// Grow handler handing out leaked memory from the host's heap, in
+    /// chunks of at least 16KB like the DOS one
+    fn grow_from_host(size: usize) -> Option<&'static mut [u8]> {
+        let memory = std::vec![0u64; size.max(16 * 1024) / 8 + 1].leak();
+
+        Some(unsafe { core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, memory.len() * 8) })
+    }
+
+    #[test]
+    fn grows_into_new_regions() {
+        let mut heap = TestHeap::new(0);
+        heap.allocator.set_grow_handler(grow_from_host);
+
+        let small = Layout::from_size_align(1000, 4).unwrap();
+        let large = Layout::from_size_align(HEAP_SIZE, 64).unwrap();
+        let mut pointers: Vec<(*mut u8, Layout)> = Vec::new();
+
+        for i in 0..200 {
+            let layout = if i % 50 == 49 { large } else { small };
+            let ptr = unsafe { heap.allocator.alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % layout.align(), 0);
+            unsafe { ptr.write_bytes(i as u8, layout.size()) };
+            pointers.push((ptr, layout));
+        }
+
+        let regions = unsafe { (*heap.allocator.regions.get()).count };
+        assert!(regions > 1);
+        assert_eq!(heap.allocator.verify(), Ok(()));
+
+        for (i, &(ptr, layout)) in pointers.iter().enumerate() {
+            assert!((0..layout.size()).all(|offset| unsafe { *ptr.add(offset) } == i as u8));
+            unsafe { heap.allocator.dealloc(ptr, layout) };
+        }
+
+        // Free blocks in different regions stay apart
+        assert_eq!(heap.allocator.verify(), Ok(()));
+        let stats = heap.allocator.stats();
+        assert_eq!(stats.used_blocks, 0);
+        assert_eq!(stats.free_blocks, regions);
+    }
+
+    #[test]
+    fn adjacent_region_extends_heap() {
+        let mut memory = std::vec![0u64; HEAP_SIZE / 8];
+        let bytes = unsafe { core::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, HEAP_SIZE) };
+        let (first_half, second_half) = bytes.split_at_mut(HEAP_SIZE / 2);
+        let mut allocator = DosAllocator::new();
+
+        unsafe {
+            allocator.init_region(first_half);
+            assert!(allocator.add_region(second_half));
+        }
+
+        let stats = allocator.stats();
+        assert_eq!(unsafe { (*allocator.regions.get()).count }, 1);
+        assert_eq!((stats.total, stats.free_blocks), (HEAP_SIZE, 1));
+        assert_eq!(allocator.verify(), Ok(()));
+
+        let layout = Layout::from_size_align(HEAP_SIZE * 3 / 4, 4).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        unsafe { allocator.dealloc(ptr, layout) };
+
+        assert_eq!(allocator.verify(), Ok(()));
+        drop(memory);
+    }
+
+    #[test]
+    fn stats_add_up() {
+        let heap = TestHeap::new(0);
+        let empty = heap.allocator.stats();
+        assert_eq!(empty.used, 0);
+        assert_eq!(empty.free, empty.total);
+        assert_eq!(empty.free_blocks, 1);
+        assert_eq!(empty.largest_free, empty.total - DosAllocator::ALLOCATOR_BLOCK_SIZE);
+
+        let small = Layout::from_size_align(40, 4).unwrap();
+        let large = Layout::from_size_align(4000, 4).unwrap();
+        let first = unsafe { heap.allocator.alloc(small) };
+        let second = unsafe { heap.allocator.alloc(large) };
+        let third = unsafe { heap.allocator.alloc(small) };
+        unsafe { heap.allocator.dealloc(second, large) };
+
+        let stats = heap.allocator.stats();
+        assert_eq!(stats.total, empty.total);
+        assert_eq!(stats.used + stats.free, stats.total);
+        assert_eq!(stats.used, 2 * (40 + DosAllocator::ALLOCATOR_BLOCK_SIZE));
+        assert_eq!(stats.used_blocks, 2);
+        assert_eq!(stats.free_blocks, 2);
+        assert!(stats.largest_free > 4000);
+
+        unsafe {
+            heap.allocator.dealloc(first, small);
+            heap.allocator.dealloc(third, small);
+        }
+
+        assert_eq!(heap.allocator.stats(), empty);
+    }
+
+    #[test]
+    #[cfg(feature = "size-classes")]
+    fn size_classes_prefer_small_blocks() {
+        let heap = TestHeap::new(0);
+        let large = Layout::from_size_align(2000, 4).unwrap();
+        let small = Layout::from_size_align(32, 4).unwrap();
+        let pointers: Vec<*mut u8> = [large, small, small, small]
+            .iter()
+            .map(|layout| unsafe { heap.allocator.alloc(*layout) })
+            .collect();
+
+        unsafe {
+            heap.allocator.dealloc(pointers[0], large);
+            heap.allocator.dealloc(pointers[2], small);
+        }
+
+        // First fit would carve this out of the large hole
+        assert_eq!(unsafe { heap.allocator.alloc(small) }, pointers[2]);
+        heap.check_blocks();
+
+        unsafe {
+            let link = FreeLists::link(DosAllocator::useful_ptr_to_block_addr(pointers[0]));
+            (*link).prev = link as *mut AllocatorBlock;
+        }
+        assert_eq!(
+            heap.allocator.verify(),
+            Err(HeapCorruption::BrokenFreeList { block: DosAllocator::useful_ptr_to_block_addr(pointers[0]) as usize })
+        );
+    }
+
+    /// Time a mix of allocations, frees and reallocs over a fragmented heap.
+    /// Run with `cargo test --release -- --ignored --nocapture benchmark`,
+    /// adding `--features size-classes` to compare the two strategies
+    #[test]
+    #[ignore]
+    fn benchmark() {
+        const OPERATIONS: usize = 200_000;
+        const MAX_LIVE: usize = 600;
+
+        let heap = TestHeap::new(0);
+        let mut random = Random(0x2545_F491_4F6C_DD1D);
+        let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+        let start = std::time::Instant::now();
+
+        for _ in 0..OPERATIONS {
+            match random.below(8) {
+                0..=3 if live.len() < MAX_LIVE => {
+                    let size = if random.below(16) == 0 { 256 + random.below(1024) } else { 1 + random.below(48) };
+                    let layout = Layout::from_size_align(size, 1 << random.below(3)).unwrap();
+                    let ptr = unsafe { heap.allocator.alloc(layout) };
+                    if !ptr.is_null() {
+                        live.push((ptr, layout));
+                    }
+                },
+                4..=5 if !live.is_empty() => {
+                    let (ptr, layout) = live.swap_remove(random.below(live.len()));
+                    unsafe { heap.allocator.dealloc(ptr, layout) };
+                },
+                6..=7 if !live.is_empty() => {
+                    let index = random.below(live.len());
+                    let (ptr, layout) = live[index];
+                    let new_size = 1 + random.below(layout.size() * 2);
+                    let new_ptr = unsafe { heap.allocator.realloc(ptr, layout, new_size) };
+                    if !new_ptr.is_null() {
+                        live[index] = (new_ptr, Layout::from_size_align(new_size, layout.align()).unwrap());
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        let elapsed = start.elapsed();
+        let stats = heap.allocator.stats();
+        std::println!(
+            "{} operations in {:?} ({:.0} ns each), {} used and {} free blocks at the end",
+            OPERATIONS,
+            elapsed,
+            elapsed.as_nanos() as f64 / OPERATIONS as f64,
+            stats.used_blocks,
+            stats.free_blocks
+        );
+
+        heap.check_blocks();
+    }
+
+    #[test]
+    fn verify_reports_corruption() {
+        assert_eq!(DosAllocator::new().verify(), Err(HeapCorruption::NotInitialized));
+
+        let layout = Layout::from_size_align(64, 4).unwrap();
+        let heap = TestHeap::new(0);
+        let pointers: Vec<*mut u8> = (0..3).map(|_| unsafe { heap.allocator.alloc(layout) }).collect();
+        let first = DosAllocator::useful_ptr_to_block_addr(pointers[0]);
+        let second = DosAllocator::useful_ptr_to_block_addr(pointers[1]);
+        let third = DosAllocator::useful_ptr_to_block_addr(pointers[2]);
+        assert_eq!(heap.allocator.verify(), Ok(()));
+
+        unsafe {
+            // Freed without merging
+            (*first).used = false;
+            (*second).used = false;
+            assert_eq!(heap.allocator.verify(), Err(HeapCorruption::UnmergedFreeBlocks { block: first as usize }));
+            (*first).used = true;
+            (*second).used = true;
+
+            let prev = (*second).prev;
+            (*second).prev = None;
+            assert_eq!(heap.allocator.verify(), Err(HeapCorruption::BrokenPrevLink { block: second as usize }));
+            (*second).prev = prev;
+
+            (*second).size += 4;
+            assert_eq!(heap.allocator.verify(), Err(HeapCorruption::BrokenNextLink { block: second as usize }));
+            (*second).size -= 4;
+
+            (*third).size = 1;
+            assert_eq!(heap.allocator.verify(), Err(HeapCorruption::BlockOutOfBounds { block: third as usize }));
+        }
+    }
+}
@@ -5,7 +5,7 @@ use alloc::string::String;
 use alloc::vec::Vec;
 
 use super::error_code::ErrorCode;
-use super::misc::ptr_to_segments;
+use super::far_ptr::{self, BufferLocation, BufferRegisters, FarPtr};
 
 #[macro_export]
 macro_rules! print {
@@ -55,24 +55,13 @@ pub fn print(string: &str) -> Result<(), ErrorCode> {
         return Err(ErrorCode::InvalidFormat)
     }
 
-    let (segment, offset) = ptr_to_segments(string.as_ptr() as u32);
+    let registers = BufferRegisters {
+        ax: 0x0900,
+        ..Default::default()
+    };
 
     unsafe {
-        asm!(
-            "mov ax, ds",
-            "push ax",          // Preserve data segment register
-            "add ax, cx",       // Offset the segment. I have no idea why
-            "mov ds, ax",
-
-            "mov ah, 0x09",     
-            "int 0x21",         // Call interrupt handler
-
-            "pop ax",           // Restore data segment register
-            "mov ds, ax",
-
-            in("cx") segment,
-            in("dx") offset,
-        )
+        far_ptr::interrupt_with_buffer(0x21, BufferLocation::DsDx, FarPtr::from_ptr(string.as_ptr()), registers);
     }
 
     Ok(())
@@ -147,23 +136,13 @@ pub fn prompt(length: u8) -> String {
     buffer.resize((length + 3) as usize, 0);
     buffer[0] = length;
 
-    let (segment, address) = ptr_to_segments(buffer.as_ptr() as u32);
+    let registers = BufferRegisters {
+        ax: 0x0a00,
+        ..Default::default()
+    };
 
     unsafe {
-        asm!(
-            "mov ax, ds",       // We need to move the data segment register to
-            "push ax",          // point to the right part of the heap that is 
-            "add ax, cx",       // holding our buffer
-            "mov ds, ax",
-
-            "mov ah, 0x0a",     
-            "int 0x21",         // Call function
-
-            "pop ax",           // Restore data segment register
-            "mov ds, ax",
-            in("dx") address,
-            in("cx") segment
-        );
+        far_ptr::interrupt_with_buffer(0x21, BufferLocation::DsDx, FarPtr::from_ptr(buffer.as_ptr()), registers);
 
         bytes_read = buffer[1] as usize;
 
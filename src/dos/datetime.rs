@@ -1,4 +1,4 @@
-use core::{arch::asm};
+use super::interrupt::{self, Registers};
 
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -45,36 +45,54 @@ pub struct Date {
 
 impl Date {
     pub fn now() -> Self {
-        let mut date = Date::default();
-        let mut day_of_week: u8;
-
-        unsafe {
-            asm!("mov ah, 0x2A",
-                "int 0x21",
-                out("cx") date.year,
-                out("dh") date.month,
-                out("dl") date.day,
-                out("al") day_of_week);
+        let mut registers = Registers::new();
+        registers.ax = 0x2a00;
+
+        // Can't fail
+        let _ = unsafe { interrupt::intdos(&mut registers) };
+
+        Date {
+            year: registers.cx,
+            month: (registers.dx >> 8) as u8,
+            day: registers.dx as u8,
+            day_of_week: Day::from(registers.al()),
         }
-        
-        date.day_of_week = Day::from(day_of_week);
+    }
 
-        date
+    /// Decode a date in the packed format DOS uses for directory entries and
+    /// file timestamps (bits 15-9: years since 1980, 8-5: month, 4-0: day)
+    pub fn from_packed(value: u16) -> Self {
+        Date {
+            day:   (value & 0b00000000_00011111) as u8,
+            month: ((value & 0b00000001_11100000) >> 5) as u8,
+            year:  ((value & 0b11111110_00000000) >> 9) + 1980,
+            ..Default::default()
+        }
     }
 
-    pub fn save(&self) -> Result<(), ()> {
-        let mut result: u8;
-
-        unsafe {
-            asm!("mov ah, 0x2B",
-                "int 0x21",
-                in("cx") self.year,
-                in("dh") self.month,
-                in("dl") self.day,
-                out("al") result);
+    /// Encode the date in the packed format, the reverse of
+    /// [Date::from_packed]. `None` for years outside of 1980 to 2107 and for
+    /// months and days out of range
+    pub fn to_packed(&self) -> Option<u16> {
+        if !(1980..=2107).contains(&self.year) || !(1..=12).contains(&self.month) || !(1..=31).contains(&self.day) {
+            return None;
         }
 
-        if result == 0 {
+        Some((self.year - 1980) << 9 |
+            (self.month as u16) << 5 |
+            self.day as u16)
+    }
+
+    pub fn save(&self) -> Result<(), ()> {
+        let mut registers = Registers::new();
+        registers.ax = 0x2b00;
+        registers.cx = self.year;
+        registers.dx = (self.month as u16) << 8 | self.day as u16;
+
+        // Reported in AL rather than the carry flag
+        let _ = unsafe { interrupt::intdos(&mut registers) };
+
+        if registers.al() == 0 {
             Ok(())
         } else {
             Err(())
@@ -98,34 +116,54 @@ pub struct Time {
 
 impl Time {
     pub fn now() -> Self {
-        let mut time = Time::default();
-
-        unsafe {
-            asm!("mov ah, 0x2C",
-                "int 0x21",
-                out("ch") time.hour,
-                out("cl") time.minute,
-                out("dh") time.second,
-                out("dl") time.centisecond);
+        let mut registers = Registers::new();
+        registers.ax = 0x2c00;
+
+        // Can't fail
+        let _ = unsafe { interrupt::intdos(&mut registers) };
+
+        Time {
+            hour: (registers.cx >> 8) as u8,
+            minute: registers.cx as u8,
+            second: (registers.dx >> 8) as u8,
+            centisecond: registers.dx as u8,
         }
+    }
 
-        time
+    /// Decode a time in the packed format DOS uses for directory entries and
+    /// file timestamps (bits 15-11: hour, 10-5: minute, 4-0: seconds / 2)
+    pub fn from_packed(value: u16) -> Self {
+        Time {
+            second: (value & 0b00000000_00011111) as u8 * 2,
+            minute: ((value & 0b00000111_11100000) >> 5) as u8,
+            hour:   ((value & 0b11111000_00000000) >> 11) as u8,
+            centisecond: 0,
+        }
     }
 
-    pub fn save(&self) -> Result<(), ()> {
-        let mut result: u8;
-
-        unsafe {
-            asm!("mov ah, 0x2D",
-            "int 0x21",
-            in("ch") self.hour,
-            in("cl") self.minute,
-            in("dh") self.second,
-            in("dl") self.centisecond,
-            out("al") result);
+    /// Encode the time in the packed format, the reverse of
+    /// [Time::from_packed]. Odd seconds are rounded down and centiseconds
+    /// are dropped. `None` if a field is out of range
+    pub fn to_packed(&self) -> Option<u16> {
+        if self.hour > 23 || self.minute > 59 || self.second > 59 {
+            return None;
         }
 
-        if result == 0 {
+        Some((self.hour as u16) << 11 |
+            (self.minute as u16) << 5 |
+            (self.second / 2) as u16)
+    }
+
+    pub fn save(&self) -> Result<(), ()> {
+        let mut registers = Registers::new();
+        registers.ax = 0x2d00;
+        registers.cx = (self.hour as u16) << 8 | self.minute as u16;
+        registers.dx = (self.second as u16) << 8 | self.centisecond as u16;
+
+        // Reported in AL rather than the carry flag
+        let _ = unsafe { interrupt::intdos(&mut registers) };
+
+        if registers.al() == 0 {
             Ok(())
         } else {
             Err(())
@@ -145,3 +183,80 @@ impl core::fmt::Debug for Time {
         write!(f, "{:02}:{:02}:{:02}.{:02}", self.hour, self.minute, self.second, self.centisecond)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dos::mock::MockBackend;
+
+    #[test]
+    fn date_now_reads_registers() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            registers.ax = 0x2a00;
+            registers.cx = 1994;
+            registers.dx = 0x0c19;
+        });
+
+        let date = Date::now();
+
+        assert_eq!(mock.calls()[0].registers.ah(), 0x2a);
+        assert_eq!((date.year, date.month, date.day), (1994, 12, 25));
+        assert!(matches!(date.day_of_week, Day::Sunday));
+    }
+
+    #[test]
+    fn date_save_reports_invalid_date() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = 0x2bff);
+
+        let date = Date {
+            year: 1994,
+            month: 13,
+            day: 1,
+            day_of_week: Day::Sunday,
+        };
+
+        assert_eq!(date.save(), Err(()));
+        assert_eq!(mock.calls()[0].registers.cx, 1994);
+        assert_eq!(mock.calls()[0].registers.dx, 0x0d01);
+    }
+
+    #[test]
+    fn time_now_reads_registers() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            registers.cx = 0x0d25;
+            registers.dx = 0x2a07;
+        });
+
+        let time = Time::now();
+
+        assert_eq!(mock.calls()[0].registers.ah(), 0x2c);
+        assert_eq!((time.hour, time.minute, time.second, time.centisecond), (13, 37, 42, 7));
+    }
+
+    #[test]
+    fn packed_round_trip() {
+        let date = Date { year: 2024, month: 2, day: 29, ..Default::default() };
+        let packed = date.to_packed().unwrap();
+        let unpacked = Date::from_packed(packed);
+
+        assert_eq!(packed, (44 << 9) | (2 << 5) | 29);
+        assert_eq!((unpacked.year, unpacked.month, unpacked.day), (2024, 2, 29));
+
+        let time = Time { hour: 23, minute: 59, second: 59, centisecond: 99 };
+        let unpacked = Time::from_packed(time.to_packed().unwrap());
+
+        assert_eq!((unpacked.hour, unpacked.minute, unpacked.second), (23, 59, 58));
+    }
+
+    #[test]
+    fn packing_rejects_out_of_range() {
+        assert_eq!(Date { year: 1979, month: 12, day: 31, ..Default::default() }.to_packed(), None);
+        assert_eq!(Date { year: 2108, month: 1, day: 1, ..Default::default() }.to_packed(), None);
+        assert_eq!(Date { year: 1994, month: 13, day: 1, ..Default::default() }.to_packed(), None);
+        assert_eq!(Time { hour: 24, ..Default::default() }.to_packed(), None);
+    }
+}
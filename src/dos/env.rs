@@ -0,0 +1,356 @@
+//! Access to the program's environment through the Program Segment Prefix
+//! (PSP).
+//!
+//! A COM program is loaded with DS pointing at its PSP, so the fields in it
+//! can be read through plain pointers to the low 256 bytes of our segment.
+//! The startup code of an EXE keeps DS there as well.
+//! The environment block lives in its own segment that the PSP points to.
+//!
+//! Reference material:
+//! * [Program Segment Prefix (Wikipedia)](https://en.wikipedia.org/wiki/Program_Segment_Prefix)
+//! * [MS-DOS Version 4.0 Programmer's Reference](https://www.pcjs.org/documents/books/mspl13/msdos/dosref40/)
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[cfg(not(test))]
+use super::far_ptr::FarPtr;
+#[cfg(not(test))]
+use super::misc;
+
+/// Offset of the command tail length byte in the PSP. The text follows it
+#[cfg(not(test))]
+const COMMAND_TAIL_OFFSET: usize = 0x80;
+/// The command tail can be at most 127 bytes including the trailing CR
+#[cfg(not(test))]
+const COMMAND_TAIL_MAX: usize = 127;
+/// Offset of the environment segment word in the PSP
+#[cfg(not(test))]
+const ENVIRONMENT_SEGMENT_OFFSET: usize = 0x2C;
+/// Environment blocks can't be larger than 32KB
+#[cfg(not(test))]
+const ENVIRONMENT_MAX: usize = 0x8000;
+/// Longest program path DOS will store after the environment
+#[cfg(not(test))]
+const PROGRAM_PATH_MAX: usize = 128;
+
+/// Iterator over the arguments the program was started with. See [args]
+pub struct Args {
+    inner: alloc::vec::IntoIter<String>,
+}
+
+impl Iterator for Args {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Args {}
+
+/// Arguments passed on the command line, split on whitespace. Double quotes
+/// group words containing spaces into a single argument and are removed.
+///
+/// Unlike `std::env::args` the program name is not included, DOS only keeps
+/// the text after it.
+///
+/// Note: DOS uses the same area of the PSP as the default disk transfer
+/// area, so anything calling find first/find next without its own DTA will
+/// overwrite the arguments. [crate::dos::file::Directory::read_dir] is safe
+/// to use.
+///
+/// ```
+/// // FOO.COM /v "my file.txt"
+/// let args: Vec<String> = env::args().collect();
+/// assert_eq!(args, ["/v", "my file.txt"]);
+/// ```
+#[cfg(not(test))]
+pub fn args() -> Args {
+    Args {
+        inner: parse_command_tail(&command_tail()).into_iter(),
+    }
+}
+
+/// Raw command tail as DOS stored it, without the length byte or trailing CR
+#[cfg(not(test))]
+pub fn command_tail() -> Vec<u8> {
+    let tail = COMMAND_TAIL_OFFSET as *const u8;
+    let mut result = Vec::new();
+
+    unsafe {
+        let length = (tail.read_volatile() as usize).min(COMMAND_TAIL_MAX);
+
+        for index in 1..=length {
+            let character = tail.add(index).read_volatile();
+
+            if character == b'\r' {
+                break;
+            }
+
+            result.push(character);
+        }
+    }
+
+    result
+}
+
+/// Split a command tail into arguments. See [args] for the rules
+pub fn parse_command_tail(tail: &[u8]) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current: Vec<u8> = Vec::new();
+    let mut in_argument = false;
+    let mut quoted = false;
+
+    for &character in tail {
+        match character {
+            b'"' => {
+                quoted = !quoted;
+                in_argument = true;
+            },
+            b' ' | b'\t' if !quoted => {
+                if in_argument {
+                    arguments.push(String::from_utf8_lossy(&current).into_owned());
+                    current.clear();
+                    in_argument = false;
+                }
+            },
+            b'\r' | b'\n' | b'\0' => break,
+            _ => {
+                current.push(character);
+                in_argument = true;
+            },
+        }
+    }
+
+    if in_argument {
+        arguments.push(String::from_utf8_lossy(&current).into_owned());
+    }
+
+    arguments
+}
+
+/// Iterator over the `KEY=VALUE` pairs in the environment. See [vars]
+pub struct Vars {
+    inner: alloc::vec::IntoIter<(String, String)>,
+}
+
+impl Iterator for Vars {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Vars {}
+
+/// All environment variables as `(key, value)` pairs, in the order DOS
+/// stores them
+#[cfg(not(test))]
+pub fn vars() -> Vars {
+    let (variables, _) = parse_environment_block(&environment_block());
+
+    Vars {
+        inner: variables.into_iter(),
+    }
+}
+
+/// Look up a single environment variable. COMMAND.COM upper-cases names
+/// given to `SET`, so the name is matched without regard to case.
+///
+/// ```
+/// let path = env::var("PATH").unwrap_or_default();
+/// ```
+#[cfg(not(test))]
+pub fn var(name: &str) -> Option<String> {
+    vars()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+/// Full path of the running program, such as `C:\TOOLS\FOO.COM`. DOS only
+/// stores this from version 3.0 onward, older versions return `None`.
+#[cfg(not(test))]
+pub fn program_path() -> Option<String> {
+    let (_, path) = parse_environment_block(&environment_block());
+
+    path
+}
+
+/// Segment holding this program's environment block, or 0 if it has none
+#[cfg(not(test))]
+pub fn environment_segment() -> u16 {
+    unsafe {
+        (ENVIRONMENT_SEGMENT_OFFSET as *const u16).read_volatile()
+    }
+}
+
+/// Copy of the raw environment block: the NUL terminated `KEY=VALUE`
+/// strings, the empty string ending them and, on DOS 3+, the string count
+/// and program path that follow
+#[cfg(not(test))]
+pub fn environment_block() -> Vec<u8> {
+    let segment = environment_segment();
+    let mut block = Vec::new();
+
+    if segment == 0 {
+        return block;
+    }
+
+    // Variables end with an empty string, so look for two NULs in a row
+    let mut offset = 0;
+    while offset < ENVIRONMENT_MAX {
+        let character = FarPtr::new(segment, offset as u16).read_byte();
+        block.push(character);
+        offset += 1;
+
+        if character == 0 && (block.len() == 1 || block[block.len() - 2] == 0) {
+            break;
+        }
+    }
+
+    if misc::dos_version().major() < 3 {
+        return block;
+    }
+
+    // Word holding the number of strings that follow, then the program path
+    for _ in 0..2 {
+        block.push(FarPtr::new(segment, offset as u16).read_byte());
+        offset += 1;
+    }
+
+    for _ in 0..PROGRAM_PATH_MAX {
+        let character = FarPtr::new(segment, offset as u16).read_byte();
+        block.push(character);
+        offset += 1;
+
+        if character == 0 {
+            break;
+        }
+    }
+
+    block
+}
+
+/// Split an environment block into its variables and the program path, if
+/// there is one. See [environment_block] for the layout
+pub fn parse_environment_block(block: &[u8]) -> (Vec<(String, String)>, Option<String>) {
+    let mut variables = Vec::new();
+    let mut position = 0;
+    let mut terminated = false;
+
+    while position < block.len() {
+        let end = block[position..].iter()
+            .position(|&c| c == 0)
+            .map_or(block.len(), |length| position + length);
+
+        if end == position {
+            position += 1;
+            terminated = true;
+            break;
+        }
+
+        let entry = &block[position..end];
+        let (key, value) = match entry.iter().position(|&c| c == b'=') {
+            Some(split) => (&entry[..split], &entry[split + 1..]),
+            None => (entry, &entry[entry.len()..]),
+        };
+
+        variables.push((
+            String::from_utf8_lossy(key).into_owned(),
+            String::from_utf8_lossy(value).into_owned()
+        ));
+
+        position = end + 1;
+    }
+
+    if !terminated || position + 2 > block.len() {
+        return (variables, None);
+    }
+
+    let count = u16::from_le_bytes([block[position], block[position + 1]]);
+    let path = &block[position + 2..];
+    let length = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+
+    if count == 0 || length == 0 {
+        return (variables, None);
+    }
+
+    (variables, Some(String::from_utf8_lossy(&path[..length]).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_command_tail() {
+        assert_eq!(parse_command_tail(b" /v  FOO.TXT\t2"), ["/v", "FOO.TXT", "2"]);
+        assert_eq!(parse_command_tail(b" /v\rGARBAGE"), ["/v"]);
+    }
+
+    #[test]
+    fn empty_command_tail() {
+        assert_eq!(parse_command_tail(b""), [""; 0]);
+        assert_eq!(parse_command_tail(b"  \t "), [""; 0]);
+        assert_eq!(parse_command_tail(b"\r"), [""; 0]);
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        assert_eq!(parse_command_tail(b" \"my file.txt\" x\"y z\""), ["my file.txt", "xy z"]);
+        assert_eq!(parse_command_tail(b" \"\" last\r"), ["", "last"]);
+        // An unclosed quote runs to the end of the tail
+        assert_eq!(parse_command_tail(b" \"C:\\MY DOCS"), ["C:\\MY DOCS"]);
+    }
+
+    #[test]
+    fn environment_with_program_path() {
+        let (variables, path) = parse_environment_block(b"PATH=C:\\DOS\0TEMP=C:\\TMP\0\0\x01\0C:\\FOO.COM\0");
+
+        assert_eq!(variables, [
+            (String::from("PATH"), String::from("C:\\DOS")),
+            (String::from("TEMP"), String::from("C:\\TMP")),
+        ]);
+        assert_eq!(path.as_deref(), Some("C:\\FOO.COM"));
+    }
+
+    #[test]
+    fn environment_without_program_path() {
+        // DOS 2 stops after the empty string
+        let (variables, path) = parse_environment_block(b"PROMPT=$P$G\0\0");
+        assert_eq!(variables, [(String::from("PROMPT"), String::from("$P$G"))]);
+        assert!(path.is_none());
+
+        // No environment at all
+        let (variables, path) = parse_environment_block(b"\0");
+        assert!(variables.is_empty());
+        assert!(path.is_none());
+
+        // A count of 0, or an empty path
+        assert!(parse_environment_block(b"A=1\0\0\0\0C:\\FOO.COM\0").1.is_none());
+        assert!(parse_environment_block(b"A=1\0\0\x01\0\0").1.is_none());
+
+        // Cut off before the empty string ending the variables
+        assert!(parse_environment_block(b"A=1\0B=2").1.is_none());
+    }
+
+    #[test]
+    fn variable_without_value() {
+        let (variables, _) = parse_environment_block(b"EMPTY=\0NOEQUALS\0\0");
+
+        assert_eq!(variables, [
+            (String::from("EMPTY"), String::new()),
+            (String::from("NOEQUALS"), String::new()),
+        ]);
+    }
+}
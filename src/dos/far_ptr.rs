@@ -0,0 +1,296 @@
+//! Segment:offset addresses
+//!
+//! Real mode memory is addressed as a 16-bit segment and a 16-bit offset,
+//! which point at the linear address `segment * 16 + offset`. Our own
+//! pointers are offsets from the data segment, so anything handed to DOS or
+//! the BIOS has to be turned into a [FarPtr] first.
+//!
+//! Interrupts that take a buffer expect it in DS:DX or ES:DI (ES:DX for some
+//! BIOS services). [interrupt_with_buffer] points the segment register at the
+//! buffer for the duration of the call and puts it back afterwards:
+//!
+//! ```
+//! let message = "Hello$";
+//! let registers = BufferRegisters { ax: 0x0900, ..Default::default() };
+//!
+//! unsafe {
+//!     interrupt_with_buffer(0x21, BufferLocation::DsDx, FarPtr::from_ptr(message.as_ptr()), registers);
+//! }
+//! ```
+
+#[cfg(not(test))]
+use core::arch::asm;
+use core::fmt;
+
+use super::interrupt::{int86, Registers};
+use super::misc;
+
+/// Bytes in a paragraph, the distance between two segments
+const SEGMENT_SIZE: u32 = 16;
+
+/// A real mode segment:offset address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FarPtr {
+    pub segment: u16,
+    pub offset: u16,
+}
+
+impl FarPtr {
+    pub const fn new(segment: u16, offset: u16) -> Self {
+        Self {
+            segment,
+            offset,
+        }
+    }
+
+    /// Normalized address of a linear address, with the offset below 16.
+    /// Anything past the first megabyte wraps around like it does on an 8086
+    pub const fn from_linear(address: u32) -> Self {
+        Self {
+            segment: (address / SEGMENT_SIZE) as u16,
+            offset: (address % SEGMENT_SIZE) as u16,
+        }
+    }
+
+    /// The linear address this points at. Can be up to 64KB past the first
+    /// megabyte (the high memory area)
+    pub const fn linear(&self) -> u32 {
+        self.segment as u32 * SEGMENT_SIZE + self.offset as u32
+    }
+
+    /// The same address with as small an offset as possible, which leaves
+    /// the most room to index forward from it
+    pub const fn normalized(&self) -> Self {
+        Self {
+            segment: self.segment.wrapping_add(self.offset / SEGMENT_SIZE as u16),
+            offset: self.offset % SEGMENT_SIZE as u16,
+        }
+    }
+
+    /// The address `count` bytes further on, normalized so it never wraps
+    /// within the segment
+    pub const fn add(&self, count: u32) -> Self {
+        Self::from_linear(self.linear() + count)
+    }
+
+    /// Far pointer to one of our own pointers, which are relative to the data
+    /// segment
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        let relative = Self::from_linear(ptr as u32);
+
+        let result = Self {
+            segment: misc::data_segment().wrapping_add(relative.segment),
+            offset: relative.offset,
+        };
+
+        // Host pointers don't fit, so the mock keeps them to read buffers
+        #[cfg(test)]
+        super::mock::remember_pointer(result, ptr as *const u8);
+
+        result
+    }
+
+    /// Our own pointer to this address. There is none for anything below the
+    /// data segment
+    pub fn to_ptr<T>(&self) -> Option<*mut T> {
+        let start = FarPtr::new(misc::data_segment(), 0).linear();
+        let relative = self.linear().checked_sub(start)?;
+
+        Some(relative as usize as *mut T)
+    }
+
+    /// Read a byte through the extra segment register, for data outside of
+    /// our own segment like the environment block
+    #[cfg(not(test))]
+    pub fn read_byte(&self) -> u8 {
+        let value: u8;
+
+        unsafe {
+            asm!(
+                "mov dx, es",       // Preserve extra segment register
+                "mov es, cx",
+                "mov al, byte ptr es:[bx]",
+                "mov es, dx",       // Restore extra segment register
+                in("cx") self.segment,
+                in("bx") self.offset,
+                out("al") value,
+                out("dx") _,
+            );
+        }
+
+        value
+    }
+
+    /// Read a little endian word. See [FarPtr::read_byte]
+    #[cfg(not(test))]
+    pub fn read_word(&self) -> u16 {
+        let value: u16;
+
+        unsafe {
+            asm!(
+                "mov dx, es",       // Preserve extra segment register
+                "mov es, cx",
+                "mov ax, word ptr es:[bx]",
+                "mov es, dx",       // Restore extra segment register
+                in("cx") self.segment,
+                in("bx") self.offset,
+                out("ax") value,
+                out("dx") _,
+            );
+        }
+
+        value
+    }
+
+    /// Write a byte through the extra segment register
+    ///
+    /// # Safety
+    ///
+    /// Nothing checks what lives at the address, it could be DOS itself
+    #[cfg(not(test))]
+    pub unsafe fn write_byte(&self, value: u8) {
+        asm!(
+            "mov dx, es",       // Preserve extra segment register
+            "mov es, cx",
+            "mov byte ptr es:[bx], al",
+            "mov es, dx",       // Restore extra segment register
+            in("cx") self.segment,
+            in("bx") self.offset,
+            in("al") value,
+            out("dx") _,
+        );
+    }
+
+    /// Write a little endian word. See [FarPtr::write_byte]
+    ///
+    /// # Safety
+    ///
+    /// Nothing checks what lives at the address, it could be DOS itself
+    #[cfg(not(test))]
+    pub unsafe fn write_word(&self, value: u16) {
+        asm!(
+            "mov dx, es",       // Preserve extra segment register
+            "mov es, cx",
+            "mov word ptr es:[bx], ax",
+            "mov es, dx",       // Restore extra segment register
+            in("cx") self.segment,
+            in("bx") self.offset,
+            in("ax") value,
+            out("dx") _,
+        );
+    }
+}
+
+/// Packed the way far pointers are stored in memory, segment in the high word
+impl From<FarPtr> for u32 {
+    fn from(value: FarPtr) -> Self {
+        (value.segment as u32) << 16 | value.offset as u32
+    }
+}
+
+impl From<u32> for FarPtr {
+    fn from(value: u32) -> Self {
+        Self {
+            segment: (value >> 16) as u16,
+            offset: value as u16,
+        }
+    }
+}
+
+impl fmt::Display for FarPtr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}:{:04X}", self.segment, self.offset)
+    }
+}
+
+/// Where an interrupt expects its buffer. See [interrupt_with_buffer]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BufferLocation {
+    /// DS:DX, used by most of INT 21h
+    DsDx,
+    /// ES:DX, used by some BIOS and mouse services
+    EsDx,
+    /// ES:DI
+    EsDi,
+}
+
+/// General purpose registers going into and coming out of
+/// [interrupt_with_buffer]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BufferRegisters {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    /// Ignored going in when the buffer offset is in DX
+    pub dx: u16,
+    /// Carry flag after the interrupt. Only meaningful for services that
+    /// report errors with it, like INT 21h
+    pub carry: bool,
+}
+
+/// Call interrupt `vector` with `buffer` loaded into the segment and offset
+/// registers picked by `location`. Built on [int86], so segment registers are
+/// restored afterwards
+///
+/// # Safety
+///
+/// `buffer` must be valid for whatever the interrupt does with it
+pub unsafe fn interrupt_with_buffer(vector: u8, location: BufferLocation, buffer: FarPtr,
+    registers: BufferRegisters) -> BufferRegisters {
+    let mut call = Registers::new();
+    call.ax = registers.ax;
+    call.bx = registers.bx;
+    call.cx = registers.cx;
+    call.dx = registers.dx;
+
+    match location {
+        BufferLocation::DsDx => call.set_ds_dx(buffer),
+        BufferLocation::EsDx => {
+            call.es = buffer.segment;
+            call.dx = buffer.offset;
+        },
+        BufferLocation::EsDi => call.set_es_di(buffer),
+    }
+
+    // Callers that care about the carry flag check it themselves
+    let _ = int86(vector, &mut call);
+
+    BufferRegisters {
+        ax: call.ax,
+        bx: call.bx,
+        cx: call.cx,
+        dx: call.dx,
+        carry: call.carry(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_round_trip() {
+        let ptr = FarPtr::new(0x1234, 0x5678);
+
+        assert_eq!(ptr.linear(), 0x179B8);
+        assert_eq!(FarPtr::from_linear(ptr.linear()), FarPtr::new(0x179B, 0x8));
+        assert_eq!(ptr.normalized(), FarPtr::new(0x179B, 0x8));
+        assert_eq!(FarPtr::new(0xFFFF, 0x10).linear(), 0x100000);
+    }
+
+    #[test]
+    fn add_crosses_segments() {
+        let ptr = FarPtr::new(0x2000, 0xFFF0);
+
+        assert_eq!(ptr.add(0x20), FarPtr::new(0x3001, 0));
+        assert_eq!(ptr.add(0x20).linear(), ptr.linear() + 0x20);
+    }
+
+    #[test]
+    fn packs_segment_high() {
+        let ptr = FarPtr::new(0xB800, 0x00A0);
+
+        assert_eq!(u32::from(ptr), 0xB80000A0);
+        assert_eq!(FarPtr::from(0xB80000A0), ptr);
+    }
+}
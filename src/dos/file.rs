@@ -1,24 +1,30 @@
+use alloc::boxed::Box;
 use bitflags::bitflags;
-use core::arch::asm;
 use crate::dos::error_code::ErrorCode;
 
-use super::{datetime::{Date, Time}, misc};
+use super::datetime::{Date, Time};
+use super::far_ptr::{self, BufferLocation, BufferRegisters, FarPtr};
+use super::interrupt::{self, Registers};
+use super::path::DosPath;
+use crate::io::{Read, Seek, Write};
 
+pub use crate::io::SeekFrom;
+
+#[cfg(not(test))]
 extern crate rlibc;
 
+/// Most bytes moved by one read or write call. Far pointers to our buffers
+/// have an offset below 16, so this never runs past the end of a segment, and
+/// it's a whole number of sectors
+const TRANSFER_CHUNK_SIZE: usize = 0xFE00;
+
 #[allow(dead_code)]
 pub struct File {
     handle: u16,
 }
-#[allow(dead_code)]
-pub enum SeekFrom {
-    Start(u32),
-    End(u32),
-    Current(u32),
-}
 
 impl SeekFrom {
-    fn to_dos_seek_code(&self) -> u8 {
+    fn to_dos_seek_code(self) -> u8 {
         match self {
             SeekFrom::Start(_) => 0,
             SeekFrom::End(_) => 2,
@@ -26,11 +32,11 @@ impl SeekFrom {
         }
     }
 
-    fn to_seek_offset(&self) -> u32 {
+    fn to_seek_offset(self) -> u32 {
         match self {
-            SeekFrom::Start(offset) => *offset,
-            SeekFrom::End(offset) => *offset,
-            SeekFrom::Current(offset) => *offset,
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) => offset,
+            SeekFrom::Current(offset) => offset,
         }
     }
 }
@@ -116,226 +122,133 @@ impl AccessMode {
 /// 
 /// Returns (ax, cx) registers or an ErrorCode
 /// 
-/// Note: The last character must be a null character or it will refuse to run
-/// with ErrorCode::InvalidParameter
-fn file_folder_helper(filename: &str, mode: u8, operation: u8) -> Result<(u16, u16), ErrorCode> {
-    let mut error_result: u8;
-    let mut error_code: u16;
-    let mut result: u16;
+/// The path is checked with [DosPath::validate] and passed in DS:DX with a
+/// NUL terminator added
+fn file_folder_helper(path: &DosPath, registers: BufferRegisters) -> Result<(u16, u16), ErrorCode> {
+    path.validate()?;
 
-    if !filename.ends_with('\0') {
-        return Err(ErrorCode::InvalidParameter);
-    }
-
-    let (segment, offset) = misc::ptr_to_segments(filename.as_ptr() as u32);
-
-    unsafe {
-        asm!(
-            "mov di, ds",
-            "push di",          // Preserve data segment register
-            "add di, cx",
-            "mov ds, di",       // Offset the segment to where our data is
+    let result = path.with_nul(|path| unsafe {
+        far_ptr::interrupt_with_buffer(0x21, BufferLocation::DsDx, FarPtr::from_ptr(path.as_ptr()), registers)
+    });
 
-            "int 0x21",
-            "setc dl",
-            "movzx cx, dl",
-
-            "pop di",           // Restore data segment register
-            "mov ds, di",
-
-            in("ah") operation,
-            in("al") mode,
-            in("cx") segment,
-            in("dx") offset,
-            lateout("dl") error_result,
-            lateout("ax") error_code,
-            lateout("cx") result);
+    if result.carry {
+        return Err(ErrorCode::from_u8(result.ax as u8).unwrap_or(ErrorCode::UnknownError));
     }
 
-    if error_result != 0 {
-        return Err(ErrorCode::from_u8(error_code as u8).unwrap_or(ErrorCode::UnknownError));
-    }
+    Ok((result.ax, result.cx))
+}
 
-    Ok((error_code, result))
+/// Registers for [file_folder_helper]: the function in AH and a subfunction
+/// or mode in AL
+fn function_registers(operation: u8, mode: u8) -> BufferRegisters {
+    BufferRegisters {
+        ax: (operation as u16) << 8 | mode as u16,
+        ..Default::default()
+    }
 }
 
 /// Enable global verification of disk writes. This will slow writing down but
 /// ensure blocks have made it to disk.
 pub fn set_verify_writes(enabled: bool) {
-    let state = if enabled {
-        1u8
-    } else {
-        0u8
-    };
+    let mut registers = Registers::new();
+    registers.ax = 0x2e00 | enabled as u16;
 
-    unsafe {
-        asm!(
-            "mov ah, 0x2e",
-            "int 0x10",
-            in("al") state
-        );
-    }
+    // Can't fail
+    let _ = unsafe { interrupt::intdos(&mut registers) };
 }
 
 /// Read if DOS is verifying writes. See [set_verify_writes]
 pub fn verify_writes() -> bool {
-    let result: u8;
+    let mut registers = Registers::new();
+    registers.ax = 0x5400;
 
-    unsafe {
-        asm!(
-            "mov ah, 54",
-            "int 0x10",
-            out("al") result
-        );
-    }
+    // Can't fail
+    let _ = unsafe { interrupt::intdos(&mut registers) };
 
-    result == 1
+    registers.al() == 1
 }
 
 
 #[allow(dead_code)]
 #[allow(unused_assignments)]
 impl File {
-    pub fn open(filename: &str, mode: AccessMode) -> Result<Self, ErrorCode> {
-        let (handle, _) = file_folder_helper(filename, mode.bits(), 0x3d)?;
+    pub fn open<P: AsRef<DosPath>>(path: P, mode: AccessMode) -> Result<Self, ErrorCode> {
+        let (handle, _) = file_folder_helper(path.as_ref(), function_registers(0x3d, mode.bits()))?;
         
         Ok(Self {
             handle,
         })
     }
 
-    pub fn create(filename: &str, attributes: FileAttributes) -> Result<Self, ErrorCode> {
-        let mut error_result: u8;
-        let mut result: u16;    
+    pub fn create<P: AsRef<DosPath>>(path: P, attributes: FileAttributes) -> Result<Self, ErrorCode> {
+        let registers = BufferRegisters {
+            cx: attributes.bits(),
+            ..function_registers(0x3c, 0x00)
+        };
+        let (handle, _) = file_folder_helper(path.as_ref(), registers)?;
 
-        if !filename.ends_with('\0') {
-            return Err(ErrorCode::InvalidParameter);
-        }
-    
-        let (segment, offset) = misc::ptr_to_segments(filename.as_ptr() as u32);
-    
-        unsafe {
-            asm!(
-                "mov di, ds",
-                "push di",          // Preserve data segment register
-                "add di, ax",
-                "mov ds, di",       // Offset the segment to where our data is
-    
-                "mov ah, 3ch",
-                "int 0x21",
-                "setc dl",
-                "movzx cx, dl",
-    
-                "pop di",           // Restore data segment register
-                "mov ds, di",
-    
-                in("ax") segment,
-                in("dx") offset,
-                in("cx") attributes.bits(),
-                lateout("dl") error_result,
-                lateout("ax") result
-            );
-        }
-    
-        if error_result != 0 {
-            return Err(ErrorCode::from_u8(result as u8).unwrap_or(ErrorCode::UnknownError));
-        }
-    
         Ok(Self {
-            handle: result,
+            handle,
         })
     }
 
-    /// Read a block of data from the currently open file
-    /// 
-    /// Will return [ErrorCode::InsufficientMemory] if the buffer provided
-    /// does not fit in the current memory segment
-    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {        
-        // Throw an error if we'd run past the current segment
-        if buffer.len() >= 0xFFF0 {
-            return Err(ErrorCode::InsufficientMemory)
-        }
-
-        let (segment, offset) = misc::ptr_to_segments(buffer.as_ptr() as u32);
-
-        let mut is_read_success: u16 = 1; // 0: success, 1: fail
-        let mut error_code_or_bytes_read: u16 = 0;
-        
-        unsafe {
-            asm!(
-                "mov ax, ds",
-                "push ax",          // Preserve data segment register
-                "add ax, di",
-                "mov ds, ax",       // Offset the segment to where our data is
-
-                "mov ah, 0x3f",
-                "int 0x21",
-
-                "setc dl",
-                "movzx cx, dl",
-
-                "pop di",           // Restore data segment register
-                "mov ds, di",
-        
-                in("cx") buffer.len() as u16,
-                in("bx") self.handle,
-                in("di") segment,
-                in("dx") offset,
-                lateout("cx") is_read_success,
-                lateout("ax") error_code_or_bytes_read
-            );
-        }
-
-        if is_read_success == 1 {
-            return Err(ErrorCode::from_u8(error_code_or_bytes_read as u8).unwrap_or(ErrorCode::UnknownError));
-        }
-
-        Ok(error_code_or_bytes_read as usize)
+    /// Read a block of data from the currently open file. Returns how many
+    /// bytes were read, which is less than `buffer.len()` at the end of the
+    /// file
+    ///
+    /// Buffers bigger than DOS can take in one call are read in several.
+    /// Should one of the later calls fail, the bytes read before it are
+    /// returned and the error is dropped. The next read tries again.
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        self.transfer(0x3f, buffer.as_mut_ptr(), buffer.len())
     }
 
-    /// Will return [ErrorCode::InsufficientMemory] if the buffer provided
-    /// does not fit in the current memory segment
+    /// Write a block of data to the currently open file. Returns how many
+    /// bytes were written, which is less than `buffer.len()` when the disk
+    /// is full. Large buffers are split up like for [File::read]
+    ///
+    /// An empty buffer is still passed to DOS, which takes a write of 0
+    /// bytes as truncating (or extending) the file at the current position
     pub fn write(&self, buffer: &[u8]) -> Result<usize, ErrorCode> {
-        // Throw an error if we'd run past the current segment
-        if buffer.len() >= 0xFFF0 {
-            return Err(ErrorCode::InsufficientMemory)
-        }
+        self.transfer(0x40, buffer.as_ptr(), buffer.len())
+    }
 
-        let (segment, offset) = misc::ptr_to_segments(buffer.as_ptr() as u32);
+    /// Read (3Fh) or write (40h) `length` bytes at `buffer`, one chunk per
+    /// call, stopping at the first short transfer. A `length` of 0 is one
+    /// call with CX = 0
+    fn transfer(&self, function: u8, buffer: *const u8, length: usize) -> Result<usize, ErrorCode> {
+        let mut total = 0;
 
-        let mut is_write_success: u16 = 1; // 0: success, 1: fail
-        let mut error_code_or_bytes_written: u16 = 0;
+        loop {
+            let chunk = (length - total).min(TRANSFER_CHUNK_SIZE);
 
-        unsafe {
-            asm!(
-                "mov ax, ds",
-                "push ax",          // Preserve data segment register
-                "add ax, di",
-                "mov ds, ax",       // Offset the segment to where our data is
+            let registers = BufferRegisters {
+                ax: (function as u16) << 8,
+                bx: self.handle,
+                cx: chunk as u16,
+                ..Default::default()
+            };
 
-                "mov ah, 0x40",
-                "int 0x21",
+            let result = unsafe {
+                far_ptr::interrupt_with_buffer(0x21, BufferLocation::DsDx, FarPtr::from_ptr(buffer.add(total)), registers)
+            };
 
-                "setc dl",
-                "movzx cx, dl",
+            if result.carry {
+                if total > 0 {
+                    break;
+                }
 
-                "pop di",           // Restore data segment register
-                "mov ds, di",
-        
-                in("cx") buffer.len() as u16,
-                in("bx") self.handle,
-                in("di") segment,
-                in("dx") offset,
-                lateout("cx") is_write_success,
-                lateout("ax") error_code_or_bytes_written
-            );
-
-            if is_write_success == 1 {
-                return Err(ErrorCode::from_u8(error_code_or_bytes_written as u8).unwrap_or(ErrorCode::UnknownError));
+                return Err(ErrorCode::from_u8(result.ax as u8).unwrap_or(ErrorCode::UnknownError));
+            }
+
+            total += result.ax as usize;
+
+            if (result.ax as usize) < chunk || total == length {
+                break;
             }
         }
 
-        Ok(error_code_or_bytes_written as usize)
+        Ok(total)
     }
 
     pub fn close(self) -> Result<(), ErrorCode> {
@@ -343,85 +256,131 @@ impl File {
     }
 
     fn close_with_ref(&self) -> Result<(), ErrorCode> {
-        let mut is_close_success: u16 = 1; // 0: success, 1: fail
-        let mut error_code: u16 = 0; // 6 = unknown handle
-        unsafe {
-            asm!("push ax", "push bx", "push cx", "push dx");
-            asm!("mov ah, 0x3e", "int 0x21", "setc  dl", "movzx cx, dl", in("bx") self.handle, lateout("cx") is_close_success, lateout("ax") error_code);
-            asm!("pop dx", "pop cx", "pop bx", "pop ax");
-        }
-        if is_close_success == 1 {
-            return Err(ErrorCode::from_u8(error_code as u8).unwrap_or(ErrorCode::UnknownError));
-        }
-        Ok(())
+        let mut registers = Registers::new();
+        registers.ax = 0x3e00;
+        registers.bx = self.handle;
+
+        unsafe { interrupt::intdos(&mut registers) }
     }
 
     /// Seek to an offset, in bytes, in a stream.
     /// Returns number of bytes from the start of the stream if success, or an error code otherwise.
     pub fn seek(&self, pos: SeekFrom) -> Result<u32, ErrorCode> {
-        let mut is_seek_success: u16 = 1; // 0: success, 1: fail
-        let mut error_code_or_new_pos_low_from_start: u16 = 0;
-        let mut new_pos_high_from_start: u16 = 0;
-        let requested_relative_new_pos: u32 = pos.to_seek_offset();
-        let requested_relative_new_pos_low = (requested_relative_new_pos & 0xffff) as u16;
-        let requested_relative_new_pos_high = ((requested_relative_new_pos >> 16) & 0xffff) as u16;
-        let seek_from: u8 = pos.to_dos_seek_code();
-        unsafe {
-            asm!("push ax", "push bx", "push cx", "push dx");
-            asm!("mov ah, 0x42", "int 0x21", "setc  dl", "movzx cx, dl", in("bx") self.handle, in("cx") requested_relative_new_pos_high as u16, in("dx") requested_relative_new_pos_low, in("al") seek_from, lateout("cx") is_seek_success, lateout("ax") error_code_or_new_pos_low_from_start, lateout("dx") new_pos_high_from_start);
-            asm!("pop dx", "pop cx", "pop bx", "pop ax");
-        }
-        if is_seek_success == 1 {
-            return Err(ErrorCode::from_u8(error_code_or_new_pos_low_from_start as u8).unwrap_or(ErrorCode::UnknownError));
+        let offset = pos.to_seek_offset();
+
+        let mut registers = Registers::new();
+        registers.ax = 0x4200 | pos.to_dos_seek_code() as u16;
+        registers.bx = self.handle;
+        registers.cx = (offset >> 16) as u16;
+        registers.dx = offset as u16;
+
+        unsafe { interrupt::intdos(&mut registers)? };
+
+        Ok((registers.dx as u32) << 16 | registers.ax as u32)
+    }
+
+    /// DOS handle number backing this file
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+
+    /// Wrap a handle that was opened elsewhere, such as one of the standard
+    /// handles 0 to 4. The handle is closed when the file is dropped, so wrap
+    /// it in [core::mem::ManuallyDrop] if it should stay open.
+    pub fn from_handle(handle: u16) -> Self {
+        Self {
+            handle,
         }
-        Ok((new_pos_high_from_start as u32) << 16 | (error_code_or_new_pos_low_from_start as u32))
     }
 
-    pub fn attributes(filename: &str) -> Result<FileAttributes, ErrorCode> {
-        let (_, attributes) = file_folder_helper(filename,  0x00, 0x43)?;
+    /// Duplicate the handle (0x45). Both handles refer to the same open file
+    /// and share the file pointer.
+    pub fn try_clone(&self) -> Result<Self, ErrorCode> {
+        let mut registers = Registers::new();
+        registers.ax = 0x4500;
+        registers.bx = self.handle;
+
+        unsafe { interrupt::intdos(&mut registers)? };
+
+        Ok(Self {
+            handle: registers.ax,
+        })
+    }
+
+    /// Make `target` refer to this file (0x46), closing whatever `target`
+    /// pointed at before. Used to redirect the standard handles.
+    pub fn duplicate_onto(&self, target: u16) -> Result<(), ErrorCode> {
+        let mut registers = Registers::new();
+        registers.ax = 0x4600;
+        registers.bx = self.handle;
+        registers.cx = target;
+
+        unsafe { interrupt::intdos(&mut registers) }
+    }
+
+    pub fn attributes<P: AsRef<DosPath>>(path: P) -> Result<FileAttributes, ErrorCode> {
+        let (_, attributes) = file_folder_helper(path.as_ref(), function_registers(0x43, 0x00))?;
         Ok(FileAttributes::from_bits_truncate(attributes))
     }
 
-    pub fn delete(filename: &str) -> Result<FileAttributes, ErrorCode> {
-        let (_, attributes) = file_folder_helper(filename,  0x00, 0x41)?;
+    /// Change the attributes of a file (43h, AL=1). Directories and volume
+    /// labels can't be made or unmade this way
+    pub fn set_attributes<P: AsRef<DosPath>>(path: P, attributes: FileAttributes) -> Result<(), ErrorCode> {
+        let registers = BufferRegisters {
+            cx: attributes.bits(),
+            ..function_registers(0x43, 0x01)
+        };
+        file_folder_helper(path.as_ref(), registers)?;
+
+        Ok(())
+    }
+
+    /// Rename or move a file (56h). Moving only works within a drive, and
+    /// files that are open can't be renamed
+    pub fn rename<P: AsRef<DosPath>, Q: AsRef<DosPath>>(from: P, to: Q) -> Result<(), ErrorCode> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        from.validate()?;
+        to.validate()?;
+
+        from.with_nul(|from| to.with_nul(|to| {
+            let mut registers = Registers::new();
+            registers.ax = 0x5600;
+            registers.set_ds_dx(FarPtr::from_ptr(from.as_ptr()));
+            registers.set_es_di(FarPtr::from_ptr(to.as_ptr()));
+
+            unsafe { interrupt::intdos(&mut registers) }
+        }))
+    }
+
+    pub fn delete<P: AsRef<DosPath>>(path: P) -> Result<FileAttributes, ErrorCode> {
+        let (_, attributes) = file_folder_helper(path.as_ref(), function_registers(0x41, 0x00))?;
         Ok(FileAttributes::from_bits_truncate(attributes))
     }
 
     pub fn last_write(&self) -> Result<(Date, Time), ErrorCode> {
-        let mut date = Date::default();
-        let mut time = Time::default();
-        let date_value: u16;
-        let time_value: u16;
-        let error_result: u8;
-        let error_code: u16;
-
-        unsafe {
-            asm!(
-                "mov al, 0x00",
-                "mov ah, 0x57",
-                "int 0x21",
-                "setc bh",
-                in ("bx") self.handle,
-                out ("ax") error_code,
-                lateout ("bh") error_result,
-                out ("cx") time_value,
-                out ("dx") date_value,
-            );
-        }
+        let mut registers = Registers::new();
+        registers.ax = 0x5700;
+        registers.bx = self.handle;
 
-        time.second = ((time_value & 0b00000000_00011111) >> 0) as u8 * 2;
-        time.minute = ((time_value & 0b00000111_11100000) >> 5) as u8;
-        time.hour =   ((time_value & 0b11111000_00011111) >> 11) as u8;
+        unsafe { interrupt::intdos(&mut registers)? };
 
-        date.day   = ((date_value & 0b00000000_00011111) >> 0) as u8;
-        date.month = ((date_value & 0b00000001_11100000) >> 5) as u8;
-        date.year  = ((date_value & 0b11111110_00000000) >> 9) + 1980;
-
-        if error_result != 0 {
-            return Err(ErrorCode::from_u8(error_code as u8).unwrap_or(ErrorCode::UnknownError));
-        }
+        Ok((Date::from_packed(registers.dx), Time::from_packed(registers.cx)))
+    }
 
-        Ok((date, time))
+    /// Set the date and time of the last write (57h, AL=1). DOS keeps the
+    /// time to 2 seconds, see [Time::to_packed]. Fails with
+    /// [ErrorCode::InvalidParameter] if the date or time can't be stored
+    ///
+    /// Call it after the last write, since writing again sets the time to
+    /// when the file is closed.
+    pub fn set_last_write(&self, date: Date, time: Time) -> Result<(), ErrorCode> {
+        let mut registers = Registers::new();
+        registers.ax = 0x5701;
+        registers.bx = self.handle;
+        registers.cx = time.to_packed().ok_or(ErrorCode::InvalidParameter)?;
+        registers.dx = date.to_packed().ok_or(ErrorCode::InvalidParameter)?;
+
+        unsafe { interrupt::intdos(&mut registers) }
     }
 }
 
@@ -431,26 +390,277 @@ impl Drop for File {
     }
 }
 
+// Like std, the traits are implemented for &File too, since reading and
+// writing only need a shared reference
+
+impl Read for File {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        File::read(self, buffer)
+    }
+}
+
+impl Read for &File {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        File::read(self, buffer)
+    }
+}
+
+/// DOS writes straight through to its own buffers, so there's nothing to
+/// flush
+impl Write for File {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+        File::write(self, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+impl Write for &File {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+        File::write(self, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, position: SeekFrom) -> Result<u32, ErrorCode> {
+        File::seek(self, position)
+    }
+}
+
+impl Seek for &File {
+    fn seek(&mut self, position: SeekFrom) -> Result<u32, ErrorCode> {
+        File::seek(self, position)
+    }
+}
+
 pub struct Directory {}
 
 impl Directory {
-    pub fn make(path: &str) -> Result<(), ErrorCode> {
-        file_folder_helper(path, 0x00, 0x39)?;
+    pub fn make<P: AsRef<DosPath>>(path: P) -> Result<(), ErrorCode> {
+        file_folder_helper(path.as_ref(), function_registers(0x39, 0x00))?;
 
         Ok(())
     }
 
-    pub fn change_current(path: &str) -> Result<(), ErrorCode> {
-        file_folder_helper(path, 0x00, 0x3b)?;
+    pub fn change_current<P: AsRef<DosPath>>(path: P) -> Result<(), ErrorCode> {
+        file_folder_helper(path.as_ref(), function_registers(0x3b, 0x00))?;
 
         Ok(())
     }
 
-    pub fn remove(path: &str) -> Result<(), ErrorCode> {
-        file_folder_helper(path, 0x00, 0x3a)?;
+    pub fn remove<P: AsRef<DosPath>>(path: P) -> Result<(), ErrorCode> {
+        file_folder_helper(path.as_ref(), function_registers(0x3a, 0x00))?;
 
         Ok(())
     }
+
+    /// List the entries matching a wildcard pattern such as `"C:\\*.*"`
+    /// (0x4E/0x4F). Attributes select which hidden, system, volume label and
+    /// directory entries are included on top of normal files.
+    ///
+    /// A pattern that matches nothing produces an empty iterator rather than
+    /// an error.
+    ///
+    /// ```
+    /// for entry in Directory::read_dir("*.TXT", FileAttributes::NORMAL)? {
+    ///     let entry = entry?;
+    ///     println!("{} {}", entry.name(), entry.size());
+    /// }
+    /// ```
+    pub fn read_dir<P: AsRef<DosPath>>(pattern: P, attributes: FileAttributes) -> Result<ReadDir, ErrorCode> {
+        let pattern = pattern.as_ref();
+        pattern.validate_pattern()?;
+
+        let mut dta = Box::new(DiskTransferArea::default());
+        let result = with_disk_transfer_area(&mut dta, || {
+            find_first(pattern, attributes)
+        });
+
+        match result {
+            Ok(()) => Ok(ReadDir {
+                dta,
+                pending: true,
+                finished: false,
+            }),
+            Err(ErrorCode::NoMoreFiles) | Err(ErrorCode::FileNotFound) => Ok(ReadDir {
+                dta,
+                pending: false,
+                finished: true,
+            }),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Disk transfer area filled in by find first/find next
+#[repr(C, packed)]
+#[derive(Default)]
+struct DiskTransferArea {
+    reserved: [u8; 21],
+    attributes: u8,
+    time: u16,
+    date: u16,
+    size: u32,
+    name: [u8; 13],
+}
+
+/// A single result from [Directory::read_dir]
+pub struct DirEntry {
+    name: [u8; 13],
+    name_length: usize,
+    size: u32,
+    attributes: FileAttributes,
+    date: Date,
+    time: Time,
+}
+
+impl DirEntry {
+    fn from_disk_transfer_area(dta: &DiskTransferArea) -> Self {
+        let name = dta.name;
+        let name_length = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+        Self {
+            name,
+            name_length,
+            size: dta.size,
+            attributes: FileAttributes::from_bits_truncate(dta.attributes as u16),
+            date: Date::from_packed(dta.date),
+            time: Time::from_packed(dta.time),
+        }
+    }
+
+    /// File name in 8.3 format, without a NUL terminator
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_length]).unwrap_or("")
+    }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn attributes(&self) -> FileAttributes {
+        self.attributes
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// Date the file was last written to
+    pub fn date(&self) -> &Date {
+        &self.date
+    }
+
+    /// Time the file was last written to
+    pub fn time(&self) -> &Time {
+        &self.time
+    }
+}
+
+/// Iterator over the entries returned by [Directory::read_dir]
+pub struct ReadDir {
+    dta: Box<DiskTransferArea>,
+    pending: bool,
+    finished: bool,
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        // Find first already filled in the DTA with the first match
+        if self.pending {
+            self.pending = false;
+            return Some(Ok(DirEntry::from_disk_transfer_area(&self.dta)));
+        }
+
+        match with_disk_transfer_area(&mut self.dta, find_next) {
+            Ok(()) => Some(Ok(DirEntry::from_disk_transfer_area(&self.dta))),
+            Err(ErrorCode::NoMoreFiles) => {
+                self.finished = true;
+                None
+            },
+            Err(error) => {
+                self.finished = true;
+                Some(Err(error))
+            },
+        }
+    }
+}
+
+/// Point DOS at our own disk transfer area for the duration of `operation`
+/// and then put the caller's one back, so anything else relying on the DTA
+/// (like the command tail in the PSP) is left alone
+fn with_disk_transfer_area<F>(dta: &mut DiskTransferArea, operation: F) -> Result<(), ErrorCode>
+    where F: FnOnce() -> Result<(), ErrorCode> {
+    let old = disk_transfer_address();
+
+    set_disk_transfer_address(FarPtr::from_ptr(dta as *const DiskTransferArea));
+    let result = operation();
+    set_disk_transfer_address(old);
+
+    result
+}
+
+/// Set the disk transfer address (0x1A)
+fn set_disk_transfer_address(dta: FarPtr) {
+    let registers = BufferRegisters {
+        ax: 0x1a00,
+        ..Default::default()
+    };
+
+    unsafe {
+        far_ptr::interrupt_with_buffer(0x21, BufferLocation::DsDx, dta, registers);
+    }
+}
+
+/// Read the current disk transfer address (0x2F)
+fn disk_transfer_address() -> FarPtr {
+    let mut registers = Registers::new();
+    registers.ax = 0x2f00;
+
+    // Can't fail
+    let _ = unsafe { interrupt::intdos(&mut registers) };
+
+    registers.es_bx()
+}
+
+/// Find first matching file (0x4E). Results land in the current DTA
+fn find_first(pattern: &DosPath, attributes: FileAttributes) -> Result<(), ErrorCode> {
+    let registers = BufferRegisters {
+        ax: 0x4e00,
+        cx: attributes.bits(),
+        ..Default::default()
+    };
+
+    let result = pattern.with_nul(|pattern| unsafe {
+        far_ptr::interrupt_with_buffer(0x21, BufferLocation::DsDx, FarPtr::from_ptr(pattern.as_ptr()), registers)
+    });
+
+    if result.carry {
+        return Err(ErrorCode::from_u8(result.ax as u8).unwrap_or(ErrorCode::UnknownError));
+    }
+
+    Ok(())
+}
+
+/// Find next matching file (0x4F) using the state left in the current DTA
+fn find_next() -> Result<(), ErrorCode> {
+    let mut registers = Registers::new();
+    registers.ax = 0x4f00;
+
+    unsafe { interrupt::intdos(&mut registers) }
 }
 
 #[derive(Debug, Default)]
@@ -465,25 +675,23 @@ impl StorageParameters {
     /// Report total and free disk space. Returns either disk storage
     /// information or InvalidDrive
     pub fn disk_space(disk_id: u8) -> Result<Self, ErrorCode> {
-        let mut value = Self::default();
-        
-        unsafe {
-            asm!(
-                "mov ah, 0x36",
-                "int 0x10",
-                in("dl") disk_id,
-                lateout("ax") value.sectors_per_cluster,
-                lateout("bx") value.available_clusters,
-                lateout("cx") value.bytes_per_sector,
-                lateout("dx") value.total_clusters,
-            );
-        }
+        let mut registers = Registers::new();
+        registers.ax = 0x3600;
+        registers.dx = disk_id as u16;
 
-        if value.sectors_per_cluster == 0xffff {
+        // Reported with AX = FFFFh rather than the carry flag
+        let _ = unsafe { interrupt::intdos(&mut registers) };
+
+        if registers.ax == 0xffff {
             return Err(ErrorCode::InvalidDrive);
         }
-        
-        Ok(value)
+
+        Ok(Self {
+            sectors_per_cluster: registers.ax,
+            available_clusters: registers.bx,
+            bytes_per_sector: registers.cx,
+            total_clusters: registers.dx,
+        })
     }
 
     /// Calculate free disk space from disk paramters. DOSBox can return more
@@ -501,4 +709,271 @@ impl StorageParameters {
         self.sectors_per_cluster as u64 * 
         self.bytes_per_sector as u64
     }
-} 
\ No newline at end of file
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::ManuallyDrop;
+    use crate::dos::mock::MockBackend;
+
+    #[test]
+    fn open_passes_name_and_mode() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            // The NUL terminated copy only lives as long as the call
+            let name = unsafe { MockBackend::read_string(FarPtr::new(registers.ds, registers.dx)) };
+            assert_eq!(name, b"README.TXT\0");
+            registers.ax = 5;
+        });
+        mock.reply_ok(|_| ());
+
+        let mode = AccessMode::new(AccessCode::Both, SharingMode::DenyWrite, false);
+        let file = File::open("README.TXT", mode).unwrap();
+        assert_eq!(file.handle(), 5);
+        drop(file);
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].vector, 0x21);
+        assert_eq!(calls[0].registers.ax, 0x3d22);
+
+        // Closed when dropped
+        assert_eq!(calls[1].registers.ah(), 0x3e);
+        assert_eq!(calls[1].registers.bx, 5);
+    }
+
+    #[test]
+    fn open_reports_carry_error() {
+        let mock = MockBackend::install();
+        mock.reply_error(2);
+
+        let result = File::open("MISSING.TXT\0", AccessMode::default());
+
+        assert!(matches!(result, Err(ErrorCode::FileNotFound)));
+        assert_eq!(mock.calls().len(), 1);
+    }
+
+    #[test]
+    fn open_checks_name() {
+        let mock = MockBackend::install();
+
+        let result = File::open("README.TEXT", AccessMode::default());
+
+        assert!(matches!(result, Err(ErrorCode::PathNotFound)));
+        assert!(mock.calls().is_empty());
+    }
+
+    #[test]
+    fn seek_splits_offset() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            registers.dx = 0x0004;
+            registers.ax = 0x5678;
+        });
+
+        let file = ManuallyDrop::new(File::from_handle(7));
+
+        assert_eq!(file.seek(SeekFrom::End(0x0001_2345)), Ok(0x0004_5678));
+
+        let call = mock.calls()[0];
+        assert_eq!(call.registers.ax, 0x4202);
+        assert_eq!(call.registers.bx, 7);
+        assert_eq!(call.registers.cx, 0x0001);
+        assert_eq!(call.registers.dx, 0x2345);
+    }
+
+    #[test]
+    fn seek_reports_carry_error() {
+        let mock = MockBackend::install();
+        mock.reply_error(6);
+
+        let file = ManuallyDrop::new(File::from_handle(99));
+
+        assert_eq!(file.seek(SeekFrom::Start(0)), Err(ErrorCode::InvalidHandle));
+    }
+
+    #[test]
+    fn last_write_unpacks_date_and_time() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            registers.dx = (14 << 9) | (12 << 5) | 25;
+            registers.cx = (13 << 11) | (37 << 5) | 21;
+        });
+
+        let file = ManuallyDrop::new(File::from_handle(5));
+        let (date, time) = file.last_write().unwrap();
+
+        assert_eq!(mock.calls()[0].registers.ax, 0x5700);
+        assert_eq!((date.year, date.month, date.day), (1994, 12, 25));
+        assert_eq!((time.hour, time.minute, time.second), (13, 37, 42));
+    }
+
+    #[test]
+    fn disk_space_reads_clusters() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            registers.ax = 4;
+            registers.bx = 100;
+            registers.cx = 512;
+            registers.dx = 1000;
+        });
+
+        let parameters = StorageParameters::disk_space(3).unwrap();
+
+        let call = mock.calls()[0];
+        assert_eq!(call.vector, 0x21);
+        assert_eq!(call.registers.ah(), 0x36);
+        assert_eq!(call.registers.dx & 0xff, 3);
+        assert_eq!(parameters.free_space(), 4 * 100 * 512);
+        assert_eq!(parameters.total_space(), 4 * 1000 * 512);
+    }
+
+    #[test]
+    fn disk_space_rejects_invalid_drive() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = 0xffff);
+
+        assert!(matches!(StorageParameters::disk_space(26), Err(ErrorCode::InvalidDrive)));
+    }
+
+    #[test]
+    fn read_exact_repeats_short_reads() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = 3);
+        mock.reply_ok(|registers| registers.ax = 2);
+        mock.reply_ok(|registers| registers.ax = 0);
+
+        let mut file = ManuallyDrop::new(File::from_handle(5));
+        let mut buffer = [0; 5];
+        Read::read_exact(&mut *file, &mut buffer).unwrap();
+
+        let calls = mock.calls();
+        assert_eq!(calls[0].registers.ax, 0x3f00);
+        assert_eq!(calls[0].registers.cx, 5);
+        assert_eq!(calls[1].registers.cx, 2);
+        assert_eq!(calls[1].registers.dx, calls[0].registers.dx.wrapping_add(3));
+
+        assert_eq!(Read::read_exact(&mut *file, &mut buffer), Err(ErrorCode::CannotCompleteFileOperation));
+    }
+
+    #[test]
+    fn large_reads_are_split() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = registers.cx);
+        mock.reply_ok(|registers| registers.ax = registers.cx);
+        mock.reply_ok(|registers| registers.ax = 0x1000);
+
+        let file = ManuallyDrop::new(File::from_handle(5));
+        let mut buffer = vec![0; 200 * 1024];
+
+        assert_eq!(file.read(&mut buffer), Ok(2 * TRANSFER_CHUNK_SIZE + 0x1000));
+
+        let calls = mock.calls();
+        // The end of the file came in the third chunk, so there's no fourth
+        assert_eq!(calls.len(), 3);
+
+        for (index, call) in calls.iter().enumerate() {
+            let address = FarPtr::new(call.registers.ds, call.registers.dx);
+            assert_eq!(address, FarPtr::from_ptr(buffer[index * TRANSFER_CHUNK_SIZE..].as_ptr()));
+            assert!(address.offset < 16);
+            assert_eq!(call.registers.cx as usize, TRANSFER_CHUNK_SIZE);
+        }
+    }
+
+    #[test]
+    fn short_write_stops_early() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = 0x100);
+
+        let file = ManuallyDrop::new(File::from_handle(5));
+        let buffer = vec![0; TRANSFER_CHUNK_SIZE + 1];
+
+        assert_eq!(file.write(&buffer), Ok(0x100));
+        assert_eq!(mock.calls()[0].registers.ax, 0x4000);
+        assert_eq!(mock.calls().len(), 1);
+    }
+
+    #[test]
+    fn empty_write_reaches_dos() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = 0);
+
+        let file = ManuallyDrop::new(File::from_handle(5));
+
+        assert_eq!(file.write(&[]), Ok(0));
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].registers.ax, 0x4000);
+        assert_eq!(calls[0].registers.cx, 0);
+    }
+
+    #[test]
+    fn error_after_progress_returns_progress() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| registers.ax = registers.cx);
+        mock.reply_error(5);
+        mock.reply_error(5);
+
+        let file = ManuallyDrop::new(File::from_handle(5));
+        let mut buffer = vec![0; TRANSFER_CHUNK_SIZE * 2];
+
+        assert_eq!(file.read(&mut buffer), Ok(TRANSFER_CHUNK_SIZE));
+        assert_eq!(file.read(&mut buffer), Err(ErrorCode::AccessDenied));
+    }
+
+    #[test]
+    fn rename_passes_both_names() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|registers| {
+            let (from, to) = unsafe {
+                (MockBackend::read_string(FarPtr::new(registers.ds, registers.dx)),
+                    MockBackend::read_string(FarPtr::new(registers.es, registers.di)))
+            };
+            assert_eq!(from, b"OLD.TXT\0");
+            assert_eq!(to, b"C:\\NEW\\NEW.TXT\0");
+        });
+
+        File::rename("OLD.TXT", "C:\\NEW\\NEW.TXT").unwrap();
+
+        assert_eq!(mock.calls()[0].registers.ax, 0x5600);
+
+        assert_eq!(File::rename("OLD.TXT", "NEW.TEXT"), Err(ErrorCode::PathNotFound));
+        assert_eq!(mock.calls().len(), 1);
+    }
+
+    #[test]
+    fn set_attributes_passes_bits() {
+        let mock = MockBackend::install();
+        mock.reply_error(5);
+
+        let attributes = FileAttributes::READ_ONLY | FileAttributes::HIDDEN;
+        assert_eq!(File::set_attributes("CONFIG.SYS", attributes), Err(ErrorCode::AccessDenied));
+
+        let call = mock.calls()[0];
+        assert_eq!(call.registers.ax, 0x4301);
+        assert_eq!(call.registers.cx, attributes.bits());
+    }
+
+    #[test]
+    fn set_last_write_packs_date_and_time() {
+        let mock = MockBackend::install();
+        mock.reply_ok(|_| ());
+
+        let file = ManuallyDrop::new(File::from_handle(5));
+        let date = Date { year: 1994, month: 12, day: 25, ..Default::default() };
+        let time = Time { hour: 13, minute: 37, second: 42, centisecond: 0 };
+        file.set_last_write(date, time).unwrap();
+
+        let call = mock.calls()[0];
+        assert_eq!(call.registers.ax, 0x5701);
+        assert_eq!(call.registers.bx, 5);
+        assert_eq!(call.registers.dx, (14 << 9) | (12 << 5) | 25);
+        assert_eq!(call.registers.cx, (13 << 11) | (37 << 5) | 21);
+
+        let date = Date { year: 1970, month: 1, day: 1, ..Default::default() };
+        assert_eq!(file.set_last_write(date, Time::default()), Err(ErrorCode::InvalidParameter));
+        assert_eq!(mock.calls().len(), 1);
+    }
+}
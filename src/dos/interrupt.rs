@@ -0,0 +1,222 @@
+//! Register level interrupt calls
+//!
+//! For services the crate doesn't wrap yet, [int86] calls any interrupt with
+//! a full set of registers, like `int86x` from Turbo C. [intdos] does the same
+//! for INT 21h and turns a set carry flag into an [ErrorCode].
+//!
+//! ```
+//! // Get the current drive (19h)
+//! let mut registers = Registers::new();
+//! registers.ax = 0x1900;
+//!
+//! unsafe { intdos(&mut registers)? };
+//! println!("Drive {}:", (b'A' + registers.al()) as char);
+//! ```
+//!
+//! Everything goes through an [InterruptBackend]. On DOS that is the [Cpu],
+//! in host tests it is the scripted backend from [crate::dos::mock], so
+//! wrappers built on [int86] can be tested with `cargo test`.
+
+#[cfg(not(test))]
+use core::arch::asm;
+#[cfg(not(test))]
+use core::mem::offset_of;
+
+use super::error_code::ErrorCode;
+use super::far_ptr::FarPtr;
+use super::misc;
+
+/// Carry flag in [Registers::flags], set by most services on failure
+pub const CARRY_FLAG: u16 = 0x0001;
+/// Zero flag in [Registers::flags]
+pub const ZERO_FLAG: u16 = 0x0040;
+
+/// Registers going into and coming out of [int86]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    pub dx: u16,
+    pub si: u16,
+    pub di: u16,
+    pub ds: u16,
+    pub es: u16,
+    /// Flags after the interrupt. Ignored going in
+    pub flags: u16,
+}
+
+impl Registers {
+    /// All zero, except for DS and ES which point at our data segment
+    pub fn new() -> Self {
+        let segment = misc::data_segment();
+
+        Self {
+            ax: 0,
+            bx: 0,
+            cx: 0,
+            dx: 0,
+            si: 0,
+            di: 0,
+            ds: segment,
+            es: segment,
+            flags: 0,
+        }
+    }
+
+    pub fn al(&self) -> u8 {
+        self.ax as u8
+    }
+
+    pub fn ah(&self) -> u8 {
+        (self.ax >> 8) as u8
+    }
+
+    /// Whether the carry flag was set when the interrupt returned
+    pub fn carry(&self) -> bool {
+        self.flags & CARRY_FLAG != 0
+    }
+
+    /// Point DS:DX at a buffer
+    pub fn set_ds_dx(&mut self, buffer: FarPtr) {
+        self.ds = buffer.segment;
+        self.dx = buffer.offset;
+    }
+
+    /// Point ES:DI at a buffer
+    pub fn set_es_di(&mut self, buffer: FarPtr) {
+        self.es = buffer.segment;
+        self.di = buffer.offset;
+    }
+
+    /// The buffer ES:BX points at, where a lot of services return one
+    pub fn es_bx(&self) -> FarPtr {
+        FarPtr::new(self.es, self.bx)
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Something that can run an interrupt. See [int86]
+pub trait InterruptBackend {
+    /// Call interrupt `vector`, updating `registers` with whatever it
+    /// returned
+    ///
+    /// # Safety
+    ///
+    /// See [int86]
+    unsafe fn call(&self, vector: u8, registers: &mut Registers);
+}
+
+/// Runs interrupts with the INT instruction
+#[cfg(not(test))]
+pub struct Cpu;
+
+#[cfg(not(test))]
+impl InterruptBackend for Cpu {
+    unsafe fn call(&self, vector: u8, registers: &mut Registers) {
+        // The vector is patched into the INT instruction, there is no form
+        // of INT that takes it from a register. The jump flushes the prefetch
+        // queue so older CPUs don't run the stale instruction
+        asm!(
+            "push ebp",                             // Keep what the compiler relies on
+            "push esi",
+            "push ds",                              // Preserve segment registers
+            "push es",
+            "push ebx",                             // Keep the registers pointer
+
+            "mov byte ptr cs:[3f + 1], al",
+            "jmp 2f",
+            "2:",
+
+            "mov ax, word ptr [ebx + {ax}]",
+            "mov cx, word ptr [ebx + {cx}]",
+            "mov dx, word ptr [ebx + {dx}]",
+            "mov si, word ptr [ebx + {si}]",
+            "mov di, word ptr [ebx + {di}]",
+            "mov es, word ptr [ebx + {es}]",
+            "push word ptr [ebx + {ds}]",
+            "mov bx, word ptr [ebx + {bx}]",        // Nothing left to read, so last
+            "pop ds",
+
+            "3:",
+            "int 0x00",
+
+            "pushf",
+            "push es",
+            "push ds",
+            "push bx",
+            "mov ds, word ptr ss:[esp + 14]",       // Our data segment again
+            "mov ebx, dword ptr ss:[esp + 8]",      // and the registers pointer
+
+            "mov word ptr [ebx + {ax}], ax",
+            "mov word ptr [ebx + {cx}], cx",
+            "mov word ptr [ebx + {dx}], dx",
+            "mov word ptr [ebx + {si}], si",
+            "mov word ptr [ebx + {di}], di",
+            "pop word ptr [ebx + {bx}]",
+            "pop word ptr [ebx + {ds}]",
+            "pop word ptr [ebx + {es}]",
+            "pop word ptr [ebx + {flags}]",
+
+            "pop ebx",
+            "pop es",                               // Restore segment registers
+            "pop ds",
+            "pop esi",
+            "pop ebp",
+
+            ax = const offset_of!(Registers, ax),
+            bx = const offset_of!(Registers, bx),
+            cx = const offset_of!(Registers, cx),
+            dx = const offset_of!(Registers, dx),
+            si = const offset_of!(Registers, si),
+            di = const offset_of!(Registers, di),
+            ds = const offset_of!(Registers, ds),
+            es = const offset_of!(Registers, es),
+            flags = const offset_of!(Registers, flags),
+            inout("eax") vector as u32 => _,
+            inout("ebx") registers as *mut Registers => _,
+            out("ecx") _,
+            out("edx") _,
+            out("edi") _,
+        );
+    }
+}
+
+/// Call interrupt `vector` with `registers`, which are updated with whatever
+/// the interrupt returned. Our own DS and ES are restored afterwards. If the
+/// carry flag is set, AX is returned as the error
+///
+/// Calls that don't return with the stack intact, like EXEC on DOS 2, need
+/// their own wrapper. See [crate::dos::process]
+///
+/// # Safety
+///
+/// Nothing stops the interrupt from writing anywhere, so the segment:offset
+/// pairs in `registers` must be valid for whatever it does with them
+pub unsafe fn int86(vector: u8, registers: &mut Registers) -> Result<(), u16> {
+    #[cfg(not(test))]
+    Cpu.call(vector, registers);
+    #[cfg(test)]
+    super::mock::call(vector, registers);
+
+    if registers.carry() {
+        return Err(registers.ax);
+    }
+
+    Ok(())
+}
+
+/// [int86] for INT 21h, with the error in AX turned into an [ErrorCode]
+///
+/// # Safety
+///
+/// See [int86]
+pub unsafe fn intdos(registers: &mut Registers) -> Result<(), ErrorCode> {
+    int86(0x21, registers).map_err(|error| ErrorCode::from_u8(error as u8).unwrap_or(ErrorCode::UnknownError))
+}
@@ -0,0 +1,285 @@
+//! Conventional memory blocks managed by DOS
+//!
+//! DOS hands out memory in paragraphs of 16 bytes, tracked by a chain of
+//! memory control blocks (MCBs) in front of every block. A COM program is
+//! given the largest free block when it starts, and so is an EXE built with
+//! exe.x, so it has to give some back before anything else can be allocated
+//! or another program executed.
+//!
+//! ```
+//! let mut block = MemoryBlock::allocate(0x100)?; // 4KB
+//! block.resize(0x200)?;
+//! let buffer = unsafe { block.as_mut_slice() }.unwrap();
+//! ```
+
+use core::arch::asm;
+
+use super::error_code::ErrorCode;
+use super::far_ptr::FarPtr;
+use super::misc;
+use crate::bios;
+
+/// Bytes in a paragraph, the unit DOS allocates memory in
+pub const PARAGRAPH_SIZE: usize = 16;
+/// Offset of the "top of memory" segment word in the PSP
+const PROGRAM_TOP_OFFSET: usize = 0x02;
+
+/// Where conventional memory ends according to the BIOS and DOS, as
+/// segments. See [memory_limits]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryLimits {
+    /// End of conventional memory reported by the BIOS (INT 12h)
+    pub bios_top: u16,
+    /// Start of the extended BIOS data area, if there is one (40:0Eh)
+    pub ebda: Option<u16>,
+    /// End of the memory block DOS gave the program when it started, from
+    /// the PSP. Later resizes aren't reflected here
+    pub program_top: u16,
+}
+
+impl MemoryLimits {
+    /// First segment past the memory the program may use: the lowest of the
+    /// three limits
+    pub fn top(&self) -> u16 {
+        let top = self.bios_top.min(self.program_top);
+
+        match self.ebda {
+            Some(ebda) => top.min(ebda),
+            None => top,
+        }
+    }
+
+    /// Bytes from the start of our data segment up to [MemoryLimits::top],
+    /// the most a pointer can address safely
+    pub fn top_offset(&self) -> usize {
+        self.top().saturating_sub(misc::data_segment()) as usize * PARAGRAPH_SIZE
+    }
+}
+
+/// Find out where usable conventional memory ends, instead of assuming
+/// 640KB with a 1KB EBDA
+pub fn memory_limits() -> MemoryLimits {
+    let program_top = unsafe {
+        (PROGRAM_TOP_OFFSET as *const u16).read_volatile()
+    };
+
+    MemoryLimits {
+        bios_top: bios::memory::conventional_memory_top(),
+        ebda: bios::memory::ebda_segment(),
+        program_top,
+    }
+}
+
+/// A block of memory allocated from DOS, freed again when dropped
+#[derive(Debug)]
+pub struct MemoryBlock {
+    segment: u16,
+    paragraphs: u16,
+}
+
+impl MemoryBlock {
+    /// Allocate a block of `paragraphs` paragraphs (0x48)
+    pub fn allocate(paragraphs: u16) -> Result<Self, ErrorCode> {
+        let segment = allocate(paragraphs).map_err(|(error, _)| error)?;
+
+        Ok(Self {
+            segment,
+            paragraphs,
+        })
+    }
+
+    /// Take over a block allocated some other way, which is then freed on
+    /// drop
+    ///
+    /// # Safety
+    ///
+    /// `segment` must be the start of a DOS memory block of at least
+    /// `paragraphs` paragraphs that nothing else will free
+    pub unsafe fn from_segment(segment: u16, paragraphs: u16) -> Self {
+        Self {
+            segment,
+            paragraphs,
+        }
+    }
+
+    /// Give up ownership of the block without freeing it and return its
+    /// segment
+    pub fn into_segment(self) -> u16 {
+        let segment = self.segment;
+        core::mem::forget(self);
+
+        segment
+    }
+
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
+
+    pub fn paragraphs(&self) -> u16 {
+        self.paragraphs
+    }
+
+    /// Size of the block in bytes
+    pub fn len(&self) -> usize {
+        self.paragraphs as usize * PARAGRAPH_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paragraphs == 0
+    }
+
+    /// Grow or shrink the block without moving it (0x4A). Fails with
+    /// [ErrorCode::InsufficientMemory] if the memory after the block is in
+    /// use
+    pub fn resize(&mut self, paragraphs: u16) -> Result<(), ErrorCode> {
+        resize(self.segment, paragraphs).map_err(|(error, _)| error)?;
+        self.paragraphs = paragraphs;
+
+        Ok(())
+    }
+
+    /// Pointer to the start of the block. Pointers are relative to our data
+    /// segment, so there is none for blocks that lie below it
+    pub fn as_ptr(&self) -> Option<*mut u8> {
+        segment_to_ptr(self.segment)
+    }
+
+    /// The whole block as a byte slice, see [MemoryBlock::as_ptr]
+    ///
+    /// # Safety
+    ///
+    /// The slice mustn't outlive the block, and the memory isn't
+    /// initialized
+    pub unsafe fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        let ptr = self.as_ptr()?;
+
+        Some(core::slice::from_raw_parts_mut(ptr, self.len()))
+    }
+}
+
+impl Drop for MemoryBlock {
+    fn drop(&mut self) {
+        let _ = free(self.segment);
+    }
+}
+
+/// Allocate `paragraphs` paragraphs of memory (0x48) and return the segment
+/// of the new block. On failure the size of the largest free block is
+/// returned along with the error
+pub fn allocate(paragraphs: u16) -> Result<u16, (ErrorCode, u16)> {
+    let error_result: u8;
+    let segment_or_error: u16;
+    let available: u16;
+
+    unsafe {
+        asm!(
+            "mov ah, 0x48",
+            "int 0x21",
+            "setc dl",
+            inout("bx") paragraphs => available,
+            lateout("ax") segment_or_error,
+            lateout("dl") error_result,
+        );
+    }
+
+    if error_result != 0 {
+        return Err((ErrorCode::from_u8(segment_or_error as u8).unwrap_or(ErrorCode::UnknownError), available));
+    }
+
+    Ok(segment_or_error)
+}
+
+/// Free a memory block (0x49)
+pub fn free(segment: u16) -> Result<(), ErrorCode> {
+    let error_result: u8;
+    let error_code: u16;
+
+    unsafe {
+        asm!(
+            "mov cx, es",       // Preserve extra segment register
+            "mov es, ax",
+
+            "mov ah, 0x49",
+            "int 0x21",
+            "setc dl",
+
+            "mov es, cx",       // Restore extra segment register
+            inout("ax") segment => error_code,
+            out("cx") _,
+            lateout("dl") error_result,
+        );
+    }
+
+    if error_result != 0 {
+        return Err(ErrorCode::from_u8(error_code as u8).unwrap_or(ErrorCode::UnknownError));
+    }
+
+    Ok(())
+}
+
+/// Resize a memory block (0x4A). On failure the largest size the block could
+/// have is returned along with the error
+pub fn resize(segment: u16, paragraphs: u16) -> Result<(), (ErrorCode, u16)> {
+    let error_result: u8;
+    let error_code: u16;
+    let available: u16;
+
+    unsafe {
+        asm!(
+            "mov cx, es",       // Preserve extra segment register
+            "mov es, ax",
+
+            "mov ah, 0x4a",
+            "int 0x21",
+            "setc dl",
+
+            "mov es, cx",       // Restore extra segment register
+            inout("ax") segment => error_code,
+            inout("bx") paragraphs => available,
+            out("cx") _,
+            lateout("dl") error_result,
+        );
+    }
+
+    if error_result != 0 {
+        return Err((ErrorCode::from_u8(error_code as u8).unwrap_or(ErrorCode::UnknownError), available));
+    }
+
+    Ok(())
+}
+
+/// Size in paragraphs of the largest block DOS could allocate right now
+pub fn largest_available() -> u16 {
+    match allocate(0xFFFF) {
+        Ok(segment) => {
+            // Only possible if nearly a whole megabyte is free, give it back
+            let _ = free(segment);
+            0xFFFF
+        },
+        Err((_, available)) => available,
+    }
+}
+
+/// Paragraphs from the PSP up to the end of the program's code, data and
+/// stack. For a COM program that is the whole 64KB segment, since the stack
+/// starts at the top of it. The linker script sets `_program_end`
+pub fn program_paragraphs() -> u16 {
+    extern "C" {
+        static _program_end: u8;
+    }
+
+    let end = core::ptr::addr_of!(_program_end) as usize;
+
+    end.div_ceil(PARAGRAPH_SIZE) as u16
+}
+
+/// Shrink the program's own memory block down to [program_paragraphs],
+/// returning the rest to DOS so it can be allocated or used by child programs
+pub fn shrink_program_block() -> Result<(), ErrorCode> {
+    resize(misc::data_segment(), program_paragraphs()).map_err(|(error, _)| error)
+}
+
+/// Pointer to the start of `segment`. See [MemoryBlock::as_ptr]
+pub fn segment_to_ptr(segment: u16) -> Option<*mut u8> {
+    FarPtr::new(segment, 0).to_ptr()
+}
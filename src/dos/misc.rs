@@ -1,5 +1,8 @@
+#[cfg(not(test))]
 use core::arch::asm;
 
+use super::interrupt::{self, Registers};
+
 #[derive(Default)]
 pub struct VersionInfo {
     major: u8,
@@ -8,6 +11,14 @@ pub struct VersionInfo {
 }
 
 impl VersionInfo {
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.minor
+    }
+
     /// Whether or not MS-DOS is running from ROM. DOS older than 5.00 only
     pub fn in_rom(&self) -> bool {
         self.major < 5 && self.flags & 0b00000100 != 0
@@ -26,28 +37,37 @@ impl core::fmt::Debug for VersionInfo {
     }
 }
 
-pub fn ptr_to_segments(value: u32) -> (u16, u16) {
-    let segment = value / 16;
-    let offset = value & 0xf;
+/// Segment our data lives in. This is also the segment of the PSP and of our
+/// memory block, for EXE programs too since their startup code sets them up
+/// like a COM program
+#[cfg(not(test))]
+pub fn data_segment() -> u16 {
+    let segment: u16;
+
+    unsafe {
+        asm!("mov ax, ds", out("ax") segment);
+    }
+
+    segment
+}
 
-    (segment as u16, offset as u16)
+/// Host tests have no segments, so pointers are treated as linear addresses
+#[cfg(test)]
+pub fn data_segment() -> u16 {
+    0
 }
 
+/// Get the DOS version (0x30)
 pub fn dos_version() -> VersionInfo {
-    let mut version_info = VersionInfo::default();
+    let mut registers = Registers::new();
+    registers.ax = 0x3000;
 
-    unsafe {
-        // Push for bh is from a reference that "bh" is preserved. If it doesn't
-        // need to be stored, please open a PR
-        asm!("mov ch, bh",
-            "mov ah, 0x30",
-            "int 0x21",
-            "mov bh, ch",
-            out("al") version_info.major,
-            out("ah") version_info.minor,
-            out("bh") version_info.flags
-        );
+    // Can't fail, DOS 1 just leaves AL at 0
+    let _ = unsafe { interrupt::intdos(&mut registers) };
+
+    VersionInfo {
+        major: registers.al(),
+        minor: registers.ah(),
+        flags: (registers.bx >> 8) as u8,
     }
-    
-    version_info
 }
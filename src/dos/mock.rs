@@ -0,0 +1,142 @@
+//! Scripted interrupts for host tests
+//!
+//! With `cargo test` there is no DOS to call, so [int86](super::interrupt::int86)
+//! goes to the [MockBackend] installed for the current test thread instead.
+//! Every call is recorded, and answered with the next scripted reply:
+//!
+//! ```
+//! let mock = MockBackend::install();
+//! mock.reply_ok(|registers| registers.ax = 5);    // Open (3Dh)
+//! mock.reply_ok(|_| ());                          // Close (3Eh) on drop
+//!
+//! let file = File::open("A.TXT\0", AccessMode::default()).unwrap();
+//! assert_eq!(mock.calls()[0].registers.ah(), 0x3d);
+//! ```
+//!
+//! A reply can look at a buffer passed to the interrupt with
+//! [MockBackend::read_string], which finds the host pointer that
+//! [FarPtr::from_ptr] was given for the address.
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use super::far_ptr::FarPtr;
+use super::interrupt::{InterruptBackend, Registers, CARRY_FLAG};
+
+/// How many pointers from [FarPtr::from_ptr] are remembered
+const REMEMBERED_POINTERS: usize = 16;
+
+type Reply = Box<dyn FnOnce(&mut Registers)>;
+
+/// An interrupt as it was called, before the reply changed the registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Call {
+    pub vector: u8,
+    pub registers: Registers,
+}
+
+/// Interrupt backend that records calls and replays scripted results
+#[derive(Default)]
+pub struct MockBackend {
+    calls: RefCell<Vec<Call>>,
+    replies: RefCell<VecDeque<Reply>>,
+}
+
+std::thread_local! {
+    static INSTALLED: RefCell<Option<Rc<MockBackend>>> = const { RefCell::new(None) };
+    static POINTERS: RefCell<VecDeque<(FarPtr, *const u8)>> = const { RefCell::new(VecDeque::new()) };
+}
+
+impl MockBackend {
+    /// Start a fresh backend for this thread, replacing any earlier one
+    pub fn install() -> Rc<MockBackend> {
+        let backend = Rc::new(MockBackend::default());
+        INSTALLED.with(|installed| *installed.borrow_mut() = Some(backend.clone()));
+
+        backend
+    }
+
+    /// Answer the next call by changing its registers. The carry flag is
+    /// cleared first
+    pub fn reply_ok<F>(&self, reply: F)
+        where F: FnOnce(&mut Registers) + 'static {
+        self.replies.borrow_mut().push_back(Box::new(move |registers: &mut Registers| {
+            registers.flags &= !CARRY_FLAG;
+            reply(registers);
+        }));
+    }
+
+    /// Answer the next call with the carry flag set and `error` in AX
+    pub fn reply_error(&self, error: u16) {
+        self.replies.borrow_mut().push_back(Box::new(move |registers: &mut Registers| {
+            registers.flags |= CARRY_FLAG;
+            registers.ax = error;
+        }));
+    }
+
+    /// Every call made so far, oldest first
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.borrow().clone()
+    }
+
+    /// Replies that were scripted but never used
+    pub fn pending(&self) -> usize {
+        self.replies.borrow().len()
+    }
+
+    /// The NUL terminated bytes at `address`, terminator included. Panics if
+    /// the address didn't come from a recent [FarPtr::from_ptr]
+    ///
+    /// # Safety
+    ///
+    /// The buffer must still be alive, so call it from a reply
+    pub unsafe fn read_string(address: FarPtr) -> Vec<u8> {
+        let pointer = POINTERS.with(|pointers| {
+            pointers.borrow().iter().rev()
+                .find(|(far, _)| *far == address)
+                .map(|(_, pointer)| *pointer)
+        }).unwrap_or_else(|| panic!("no host pointer for {:04X}:{:04X}", address.segment, address.offset));
+
+        let length = (0..).find(|&index| *pointer.add(index) == 0).unwrap();
+
+        std::slice::from_raw_parts(pointer, length + 1).to_vec()
+    }
+}
+
+/// Keep the host pointer behind a far pointer, for [MockBackend::read_string]
+pub(crate) fn remember_pointer(address: FarPtr, pointer: *const u8) {
+    POINTERS.with(|pointers| {
+        let mut pointers = pointers.borrow_mut();
+        if pointers.len() == REMEMBERED_POINTERS {
+            pointers.pop_front();
+        }
+
+        pointers.push_back((address, pointer));
+    });
+}
+
+impl InterruptBackend for MockBackend {
+    unsafe fn call(&self, vector: u8, registers: &mut Registers) {
+        self.calls.borrow_mut().push(Call {
+            vector,
+            registers: *registers,
+        });
+
+        let reply = self.replies.borrow_mut()
+            .pop_front()
+            .unwrap_or_else(|| panic!("unscripted interrupt {:02X}h with {:04X?}", vector, registers));
+
+        reply(registers);
+    }
+}
+
+/// Pass a call on to the backend installed for this thread
+pub(crate) unsafe fn call(vector: u8, registers: &mut Registers) {
+    let backend = INSTALLED.with(|installed| installed.borrow().clone())
+        .expect("no MockBackend installed for this test");
+
+    backend.call(vector, registers);
+}
@@ -4,5 +4,5 @@ use core::panic::PanicInfo;
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     println!("{}", _info);
-    exit(1);
+    exit(crate::EXIT_PANIC);
 }
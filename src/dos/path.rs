@@ -0,0 +1,474 @@
+//! DOS paths
+//!
+//! [DosPath] is a borrowed path, like `std::path::Path`, and [DosPathBuf] an
+//! owned one. Functions that take a path accept anything that converts into
+//! a [DosPath], plain strings included, and add the NUL terminator DOS wants
+//! themselves. A trailing `\0` is still accepted, so either of these works:
+//!
+//! ```
+//! let config = File::open("C:\\CONFIG.SYS", AccessMode::default())?;
+//! let backup = DosPath::new("C:\\").join("CONFIG.SYS").with_extension("BAK");
+//! File::create(&backup, FileAttributes::NORMAL)?;
+//! ```
+//!
+//! Before a path is handed to DOS it's checked against the 8.3 rules, since
+//! DOS would quietly cut a long name short and open the wrong file.
+
+use core::fmt;
+
+use super::error_code::ErrorCode;
+use super::{String, Vec};
+
+/// Longest path DOS takes, terminator included
+pub const MAX_PATH: usize = 128;
+
+/// Characters that can't be part of a file name. `*` and `?` are wildcards
+const INVALID_CHARACTERS: &str = "\"*+,/:;<=>?[]|";
+
+/// A borrowed DOS path such as `C:\DOS\EDIT.COM`
+#[repr(transparent)]
+pub struct DosPath {
+    inner: str,
+}
+
+impl DosPath {
+    /// Wrap a string, leaving out any NUL terminators at its end
+    pub fn new<S: AsRef<str> + ?Sized>(path: &S) -> &DosPath {
+        let path = path.as_ref().trim_end_matches('\0');
+
+        // DosPath is a transparent wrapper around str
+        unsafe { &*(path as *const str as *const DosPath) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.inner
+    }
+
+    pub fn to_path_buf(&self) -> DosPathBuf {
+        DosPathBuf::from(self.as_str())
+    }
+
+    /// Drive letter, in upper case, if the path starts with one
+    pub fn drive(&self) -> Option<char> {
+        self.split_drive().0.chars().next().map(|drive| drive.to_ascii_uppercase())
+    }
+
+    /// Whether the path starts at the root directory, rather than at the
+    /// current directory of its drive
+    pub fn is_absolute(&self) -> bool {
+        self.split_drive().1.starts_with('\\')
+    }
+
+    /// The names between the separators, without the drive
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.split_drive().1.split('\\').filter(|component| !component.is_empty())
+    }
+
+    /// The last component, unless it's `.` or `..`
+    pub fn file_name(&self) -> Option<&str> {
+        match self.components().last()? {
+            "." | ".." => None,
+            name => Some(name),
+        }
+    }
+
+    /// The file name without its extension
+    pub fn file_stem(&self) -> Option<&str> {
+        let name = self.file_name()?;
+
+        Some(self.split_extension(name).0)
+    }
+
+    /// The part of the file name after the dot, if there is one
+    pub fn extension(&self) -> Option<&str> {
+        let name = self.file_name()?;
+
+        self.split_extension(name).1
+    }
+
+    /// The path without its last component. `None` for a root directory,
+    /// a bare drive or an empty path
+    pub fn parent(&self) -> Option<&DosPath> {
+        let (drive, rest) = self.split_drive();
+        let rest = rest.trim_end_matches('\\');
+
+        if rest.is_empty() {
+            return None;
+        }
+
+        let end = match rest.rfind('\\') {
+            // Keep the separator of the root directory
+            Some(0) => 1,
+            Some(index) => index,
+            None => 0,
+        };
+
+        Some(DosPath::new(&self.inner[..drive.len() + end]))
+    }
+
+    /// `path` added to the end of this one. See [DosPathBuf::push]
+    pub fn join<P: AsRef<DosPath>>(&self, path: P) -> DosPathBuf {
+        let mut result = self.to_path_buf();
+        result.push(path);
+
+        result
+    }
+
+    /// The same path with `extension` instead of the current one. See
+    /// [DosPathBuf::set_extension]
+    pub fn with_extension(&self, extension: &str) -> DosPathBuf {
+        let mut result = self.to_path_buf();
+        result.set_extension(extension);
+
+        result
+    }
+
+    /// Check that DOS can take the path as it is: a drive letter at most at
+    /// the start, `\` separators and 8.3 names. Fails with
+    /// [ErrorCode::InvalidDrive] for a bad drive and [ErrorCode::PathNotFound]
+    /// for everything else, the same errors DOS gives
+    pub fn validate(&self) -> Result<(), ErrorCode> {
+        self.check(false)
+    }
+
+    /// Like [DosPath::validate], but allows `*` and `?` wildcards in the last
+    /// component, for searching directories
+    pub fn validate_pattern(&self) -> Result<(), ErrorCode> {
+        self.check(true)
+    }
+
+    /// Call `function` with a NUL terminated copy of the path. The copy is
+    /// on the stack unless the path is too long for DOS anyway
+    pub fn with_nul<T, F>(&self, function: F) -> T
+        where F: FnOnce(&[u8]) -> T {
+        let bytes = self.inner.as_bytes();
+
+        if bytes.len() < MAX_PATH {
+            let mut buffer = [0; MAX_PATH];
+            buffer[..bytes.len()].copy_from_slice(bytes);
+
+            function(&buffer[..=bytes.len()])
+        } else {
+            let mut buffer = Vec::with_capacity(bytes.len() + 1);
+            buffer.extend_from_slice(bytes);
+            buffer.push(0);
+
+            function(&buffer)
+        }
+    }
+
+    /// Split off the drive, `C:`, if there is one
+    fn split_drive(&self) -> (&str, &str) {
+        match self.inner.as_bytes() {
+            [_, b':', ..] => self.inner.split_at(2),
+            _ => ("", &self.inner),
+        }
+    }
+
+    /// Split a file name at its dot. A leading dot isn't an extension
+    fn split_extension<'a>(&self, name: &'a str) -> (&'a str, Option<&'a str>) {
+        match name.rfind('.') {
+            Some(index) if index > 0 => (&name[..index], Some(&name[index + 1..])),
+            _ => (name, None),
+        }
+    }
+
+    fn check(&self, wildcards: bool) -> Result<(), ErrorCode> {
+        if self.inner.is_empty() || self.inner.len() >= MAX_PATH {
+            return Err(ErrorCode::PathNotFound);
+        }
+
+        let (drive, rest) = self.split_drive();
+        if !drive.is_empty() && !drive.as_bytes()[0].is_ascii_alphabetic() {
+            return Err(ErrorCode::InvalidDrive);
+        }
+
+        let rest = rest.strip_prefix('\\').unwrap_or(rest);
+        let rest = rest.strip_suffix('\\').unwrap_or(rest);
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let count = rest.split('\\').count();
+        for (index, component) in rest.split('\\').enumerate() {
+            check_name(component, wildcards && index == count - 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Check one component of a path against the 8.3 rules
+fn check_name(name: &str, wildcards: bool) -> Result<(), ErrorCode> {
+    if name == "." || name == ".." {
+        return Ok(());
+    }
+
+    let (stem, extension) = name.split_once('.').unwrap_or((name, ""));
+
+    let valid_character = |character: char| {
+        !character.is_control() &&
+            (!INVALID_CHARACTERS.contains(character) || (wildcards && (character == '*' || character == '?')))
+    };
+
+    // DOS counts bytes, and anything that isn't ASCII takes more than one
+    let valid = !stem.is_empty() &&
+        stem.len() <= 8 &&
+        extension.len() <= 3 &&
+        !extension.contains('.') &&
+        !name.starts_with(' ') &&
+        name.chars().all(|character| character == '.' || valid_character(character));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ErrorCode::PathNotFound)
+    }
+}
+
+/// DOS doesn't care about case, so neither does comparing paths
+impl PartialEq for DosPath {
+    fn eq(&self, other: &DosPath) -> bool {
+        self.inner.eq_ignore_ascii_case(&other.inner)
+    }
+}
+
+impl Eq for DosPath {}
+
+impl fmt::Debug for DosPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl fmt::Display for DosPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.inner)
+    }
+}
+
+impl AsRef<DosPath> for DosPath {
+    fn as_ref(&self) -> &DosPath {
+        self
+    }
+}
+
+impl AsRef<DosPath> for str {
+    fn as_ref(&self) -> &DosPath {
+        DosPath::new(self)
+    }
+}
+
+impl AsRef<DosPath> for String {
+    fn as_ref(&self) -> &DosPath {
+        DosPath::new(self)
+    }
+}
+
+/// An owned DOS path, which can be built up a component at a time
+#[derive(Clone, Default)]
+pub struct DosPathBuf {
+    inner: String,
+}
+
+impl DosPathBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn as_path(&self) -> &DosPath {
+        DosPath::new(&self.inner)
+    }
+
+    pub fn into_string(self) -> String {
+        self.inner
+    }
+
+    /// Add `path` to the end, with a separator if one is needed. A `path`
+    /// with a drive or starting at the root replaces this one instead, like
+    /// `cd` would
+    pub fn push<P: AsRef<DosPath>>(&mut self, path: P) {
+        let path = path.as_ref();
+
+        if path.drive().is_some() || path.is_absolute() {
+            self.inner.clear();
+        } else if !self.inner.is_empty() && !self.inner.ends_with('\\') && !self.inner.ends_with(':') {
+            self.inner.push('\\');
+        }
+
+        self.inner.push_str(path.as_str());
+    }
+
+    /// Remove the last component. Returns false if there was none
+    pub fn pop(&mut self) -> bool {
+        match self.parent().map(|parent| parent.as_str().len()) {
+            Some(length) => {
+                self.inner.truncate(length);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Replace the extension, or remove it if `extension` is empty. Returns
+    /// false if there is no file name to change
+    pub fn set_extension(&mut self, extension: &str) -> bool {
+        let stem = match self.file_stem() {
+            Some(stem) => stem.len(),
+            None => return false,
+        };
+
+        // The file name is always at the end, barring a trailing separator
+        let trailing = self.inner.len() - self.inner.trim_end_matches('\\').len();
+        let name_start = self.inner.len() - trailing - self.file_name().map_or(0, str::len);
+        self.inner.truncate(name_start + stem);
+
+        if !extension.is_empty() {
+            self.inner.push('.');
+            self.inner.push_str(extension);
+        }
+
+        true
+    }
+}
+
+impl core::ops::Deref for DosPathBuf {
+    type Target = DosPath;
+
+    fn deref(&self) -> &DosPath {
+        self.as_path()
+    }
+}
+
+impl From<&str> for DosPathBuf {
+    fn from(path: &str) -> Self {
+        Self {
+            inner: String::from(DosPath::new(path).as_str()),
+        }
+    }
+}
+
+impl From<String> for DosPathBuf {
+    fn from(mut path: String) -> Self {
+        let length = DosPath::new(&path).as_str().len();
+        path.truncate(length);
+
+        Self {
+            inner: path,
+        }
+    }
+}
+
+impl AsRef<DosPath> for DosPathBuf {
+    fn as_ref(&self) -> &DosPath {
+        self.as_path()
+    }
+}
+
+impl PartialEq for DosPathBuf {
+    fn eq(&self, other: &DosPathBuf) -> bool {
+        self.as_path() == other.as_path()
+    }
+}
+
+impl Eq for DosPathBuf {}
+
+impl fmt::Debug for DosPathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_path(), f)
+    }
+}
+
+impl fmt::Display for DosPathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_path(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_up_paths() {
+        let path = DosPath::new("c:\\DOS\\EDIT.COM");
+
+        assert_eq!(path.drive(), Some('C'));
+        assert!(path.is_absolute());
+        assert_eq!(path.components().collect::<Vec<_>>(), ["DOS", "EDIT.COM"]);
+        assert_eq!(path.file_name(), Some("EDIT.COM"));
+        assert_eq!(path.file_stem(), Some("EDIT"));
+        assert_eq!(path.extension(), Some("COM"));
+        assert_eq!(DosPath::new("README").extension(), None);
+        assert_eq!(DosPath::new("..").file_name(), None);
+    }
+
+    #[test]
+    fn parents() {
+        let parent = |path: &'static str| DosPath::new(path).parent().map(DosPath::as_str);
+
+        assert_eq!(parent("C:\\DOS\\EDIT.COM"), Some("C:\\DOS"));
+        assert_eq!(parent("C:\\DOS\\"), Some("C:\\"));
+        assert_eq!(parent("C:\\"), None);
+        assert_eq!(parent("C:GAMES\\DOOM"), Some("C:GAMES"));
+        assert_eq!(parent("C:GAMES"), Some("C:"));
+        assert_eq!(parent("README.TXT"), Some(""));
+        assert_eq!(parent(""), None);
+    }
+
+    #[test]
+    fn joins() {
+        assert_eq!(DosPath::new("C:\\DOS").join("EDIT.COM").as_str(), "C:\\DOS\\EDIT.COM");
+        assert_eq!(DosPath::new("C:\\").join("DOS").as_str(), "C:\\DOS");
+        assert_eq!(DosPath::new("A:").join("SETUP.EXE").as_str(), "A:SETUP.EXE");
+        assert_eq!(DosPath::new("C:\\DOS").join("D:\\GAMES").as_str(), "D:\\GAMES");
+        assert_eq!(DosPath::new("C:\\DOS").join("\\TEMP").as_str(), "\\TEMP");
+
+        let mut path = DosPathBuf::from("C:\\DOS\\EDIT.COM");
+        assert!(path.pop());
+        assert!(path.pop());
+        assert!(!path.pop());
+        assert_eq!(path.as_str(), "C:\\");
+    }
+
+    #[test]
+    fn replaces_extensions() {
+        assert_eq!(DosPath::new("C:\\CONFIG.SYS").with_extension("BAK").as_str(), "C:\\CONFIG.BAK");
+        assert_eq!(DosPath::new("AUTOEXEC").with_extension("BAT").as_str(), "AUTOEXEC.BAT");
+        assert_eq!(DosPath::new("GAME.EXE").with_extension("").as_str(), "GAME");
+
+        let mut path = DosPathBuf::from("C:\\");
+        assert!(!path.set_extension("TXT"));
+    }
+
+    #[test]
+    fn validates_8_3_names() {
+        for path in ["C:\\DOS\\EDIT.COM", "README.md", "a:", "\\", "..\\SRC\\", "NUL", "C:\\1A2B3C4D"] {
+            assert_eq!(DosPath::new(path).validate(), Ok(()), "{}", path);
+        }
+
+        for path in ["", "LONGFILENAME.TXT", "FILE.TEXT", "A.B.C", "C:/DOS", "C:\\DOS\\\\EDIT", "*.TXT", "A\tB", "ÄÄÄÄÄÄÄÄ.TXT"] {
+            assert_eq!(DosPath::new(path).validate(), Err(ErrorCode::PathNotFound), "{}", path);
+        }
+
+        assert_eq!(DosPath::new("1:\\").validate(), Err(ErrorCode::InvalidDrive));
+        assert_eq!(DosPath::new("C:\\SRC\\*.RS").validate_pattern(), Ok(()));
+        assert_eq!(DosPath::new("C:\\*\\A.RS").validate_pattern(), Err(ErrorCode::PathNotFound));
+    }
+
+    #[test]
+    fn terminates_with_nul() {
+        let path = DosPath::new("C:\\AUTOEXEC.BAT\0");
+        assert_eq!(path.as_str(), "C:\\AUTOEXEC.BAT");
+        assert_eq!(path.with_nul(|bytes| bytes.to_vec()), b"C:\\AUTOEXEC.BAT\0");
+
+        let long = "A".repeat(MAX_PATH);
+        assert_eq!(DosPath::new(&long).with_nul(<[u8]>::len), MAX_PATH + 1);
+    }
+
+    #[test]
+    fn compares_without_case() {
+        assert_eq!(DosPath::new("c:\\dos"), DosPath::new("C:\\DOS\0"));
+        assert_eq!(DosPathBuf::from("readme.txt"), DosPathBuf::from("README.TXT"));
+    }
+}
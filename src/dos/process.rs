@@ -0,0 +1,440 @@
+//! Child processes and their exit statuses
+//!
+//! ```
+//! let status = Command::new("C:\\DOS\\FORMAT.COM")
+//!     .arg("A:")
+//!     .arg("/S")
+//!     .status()?;
+//!
+//! let status = Command::shell("DIR /W > FILES.TXT").status()?;
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::mem::ManuallyDrop;
+
+#[cfg(not(feature = "dos-heap"))]
+use super::allocator::GLOBAL_ALLOCATOR;
+use super::env;
+use super::error_code::ErrorCode;
+use super::far_ptr::FarPtr;
+use super::file::File;
+#[cfg(not(feature = "dos-heap"))]
+use super::memory;
+use super::misc;
+use super::path::DosPath;
+
+/// The command tail holds at most 126 characters plus the length and CR
+const COMMAND_TAIL_MAX: usize = 126;
+/// Offsets of the two default FCBs in our PSP, handed on to the child
+const FCB1_OFFSET: u16 = 0x5C;
+const FCB2_OFFSET: u16 = 0x6C;
+
+const STDIN: u16 = 0;
+const STDOUT: u16 = 1;
+const STDERR: u16 = 2;
+
+/// DOS 2 destroys every register during EXEC, including SS:SP. The stack
+/// pointer is stashed here so it can be put back afterwards
+static mut EXEC_STACK_POINTER: u16 = 0;
+
+/// How a child program ended. See [exit_status]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum TerminationType {
+    /// Exited normally with INT 21h 4Ch or by returning
+    Normal = 0,
+    /// Stopped by the user pressing Ctrl-C or Ctrl-Break
+    ControlC = 1,
+    /// Aborted from the critical error handler (INT 24h)
+    CriticalError = 2,
+    /// Stayed resident with INT 21h 31h
+    TerminateAndStayResident = 3,
+    /// Reserved value not documented by DOS
+    Unknown = 0xff,
+}
+
+impl From<u8> for TerminationType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Normal,
+            1 => Self::ControlC,
+            2 => Self::CriticalError,
+            3 => Self::TerminateAndStayResident,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Result of a child program that has finished running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExitStatus {
+    code: u8,
+    termination: TerminationType,
+}
+
+impl ExitStatus {
+    /// Value the child passed to exit, what batch files see as `ERRORLEVEL`
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn termination(&self) -> TerminationType {
+        self.termination
+    }
+
+    /// Whether the child ended normally with an exit code of 0
+    pub fn success(&self) -> bool {
+        self.termination == TerminationType::Normal && self.code == 0
+    }
+}
+
+/// Get the return code of the last child program (0x4D)
+///
+/// DOS only keeps this around for one call, asking a second time returns
+/// garbage.
+pub fn exit_status() -> ExitStatus {
+    let code: u8;
+    let termination: u8;
+
+    unsafe {
+        asm!(
+            "mov ah, 0x4d",
+            "int 0x21",
+            out("al") code,
+            out("ah") termination,
+        );
+    }
+
+    ExitStatus {
+        code,
+        termination: TerminationType::from(termination),
+    }
+}
+
+/// Parameter block for load and execute (0x4B00)
+#[repr(C, packed)]
+#[derive(Default)]
+struct ExecParameterBlock {
+    environment_segment: u16,
+    command_tail_offset: u16,
+    command_tail_segment: u16,
+    fcb1_offset: u16,
+    fcb1_segment: u16,
+    fcb2_offset: u16,
+    fcb2_segment: u16,
+}
+
+/// Builder for running another program with EXEC (0x4B)
+pub struct Command {
+    program: String,
+    args: Vec<String>,
+    environment: Option<Vec<(String, String)>>,
+    stdin: Option<u16>,
+    stdout: Option<u16>,
+    stderr: Option<u16>,
+}
+
+impl Command {
+    /// Run the program at `path`. This has to be the full name of a .COM or
+    /// .EXE file, DOS doesn't search the `PATH` or run batch files. Use
+    /// [Command::shell] for those.
+    pub fn new<P: AsRef<DosPath>>(path: P) -> Self {
+        Self {
+            program: String::from(path.as_ref().as_str()),
+            args: Vec::new(),
+            environment: None,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    /// Run a command line through the command interpreter, as in
+    /// `COMMAND.COM /C command`. The interpreter is taken from `COMSPEC`
+    pub fn shell(command: &str) -> Self {
+        let comspec = env::var("COMSPEC").unwrap_or_else(|| String::from("C:\\COMMAND.COM"));
+        let mut result = Self::new(&comspec);
+
+        result.arg("/C");
+        result.args.push(String::from(command));
+
+        result
+    }
+
+    /// Add an argument. Arguments with spaces are quoted
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(String::from(arg));
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+        where I: IntoIterator<Item = S>, S: AsRef<str> {
+        for arg in args {
+            self.arg(arg.as_ref());
+        }
+        self
+    }
+
+    /// Set a variable in the child's environment. Without any of the `env`
+    /// calls the child gets a copy of ours.
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        let environment = self.environment.get_or_insert_with(|| env::vars().collect());
+
+        match environment.iter_mut().find(|(name, _)| name.eq_ignore_ascii_case(key)) {
+            Some((_, old_value)) => *old_value = String::from(value),
+            None => environment.push((String::from(key), String::from(value))),
+        }
+
+        self
+    }
+
+    pub fn env_remove(&mut self, key: &str) -> &mut Self {
+        let environment = self.environment.get_or_insert_with(|| env::vars().collect());
+
+        environment.retain(|(name, _)| !name.eq_ignore_ascii_case(key));
+
+        self
+    }
+
+    /// Start the child with an empty environment
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.environment = Some(Vec::new());
+        self
+    }
+
+    /// Redirect the child's standard input from a file. The file has to stay
+    /// open until [Command::status] returns
+    pub fn stdin(&mut self, file: &File) -> &mut Self {
+        self.stdin = Some(file.handle());
+        self
+    }
+
+    /// Redirect the child's standard output to a file. The file has to stay
+    /// open until [Command::status] returns
+    pub fn stdout(&mut self, file: &File) -> &mut Self {
+        self.stdout = Some(file.handle());
+        self
+    }
+
+    /// Redirect the child's standard error to a file. The file has to stay
+    /// open until [Command::status] returns
+    pub fn stderr(&mut self, file: &File) -> &mut Self {
+        self.stderr = Some(file.handle());
+        self
+    }
+
+    /// Run the program and wait for it to finish.
+    ///
+    /// Our memory block is shrunk down to what the heap is using (4Ah) so the
+    /// child has somewhere to load, and grown back once it exits. If the
+    /// child stays resident and takes that memory, this returns
+    /// [ErrorCode::InsufficientMemory] and the heap must not grow any
+    /// further.
+    ///
+    /// With the `dos-heap` feature our block was already shrunk at startup
+    /// and is left alone.
+    pub fn status(&mut self) -> Result<ExitStatus, ErrorCode> {
+        DosPath::new(&self.program).validate()?;
+
+        let mut program = self.program.clone();
+        program.push('\0');
+
+        let command_tail = self.command_tail()?;
+        let environment = self.environment_block();
+
+        let data_segment = misc::data_segment();
+        let tail = FarPtr::from_ptr(command_tail.as_ptr());
+
+        let mut parameters = ExecParameterBlock {
+            command_tail_offset: tail.offset,
+            command_tail_segment: tail.segment,
+            fcb1_offset: FCB1_OFFSET,
+            fcb1_segment: data_segment,
+            fcb2_offset: FCB2_OFFSET,
+            fcb2_segment: data_segment,
+            ..Default::default()
+        };
+
+        if let Some(environment) = &environment {
+            // The environment has to start on a paragraph boundary
+            let start = (environment.as_ptr() as usize + 15) & !15;
+            parameters.environment_segment = FarPtr::from_ptr(start as *const u8).segment;
+        }
+
+        // Nothing may be allocated from here until the memory block is grown
+        // back, the heap past this point belongs to the child
+        #[cfg(not(feature = "dos-heap"))]
+        let original_paragraphs = {
+            let used_paragraphs = unsafe {
+                ((GLOBAL_ALLOCATOR.used_end() + 15) / 16) as u16
+            };
+            let keep_paragraphs = used_paragraphs.max(memory::program_paragraphs());
+            let original_paragraphs = match memory::resize(data_segment, 0xFFFF) {
+                Ok(()) => 0xFFFF,
+                Err((_, available)) => available,
+            };
+
+            memory::resize(data_segment, keep_paragraphs).map_err(|(error, _)| error)?;
+            original_paragraphs
+        };
+
+        let redirections = [
+            (STDIN, self.stdin),
+            (STDOUT, self.stdout),
+            (STDERR, self.stderr),
+        ];
+
+        let mut saved: [Option<File>; 3] = [None, None, None];
+        let mut result = Ok(());
+
+        for (index, (target, source)) in redirections.iter().enumerate() {
+            if let Some(source) = source {
+                result = redirect(*target, *source).map(|old| saved[index] = Some(old));
+
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+
+        if result.is_ok() {
+            result = exec(&program, &parameters);
+        }
+
+        for (index, (target, _)) in redirections.iter().enumerate() {
+            if let Some(old) = saved[index].take() {
+                let _ = old.duplicate_onto(*target);
+            }
+        }
+
+        #[cfg(not(feature = "dos-heap"))]
+        let regrow = memory::resize(data_segment, original_paragraphs);
+
+        result?;
+        let status = exit_status();
+        #[cfg(not(feature = "dos-heap"))]
+        regrow.map_err(|_| ErrorCode::InsufficientMemory)?;
+
+        Ok(status)
+    }
+
+    /// Build the command tail: a length byte, the arguments each preceded by
+    /// a space, and a CR that isn't counted in the length
+    fn command_tail(&self) -> Result<Vec<u8>, ErrorCode> {
+        let mut tail = Vec::with_capacity(COMMAND_TAIL_MAX + 2);
+        tail.push(0);
+
+        for arg in &self.args {
+            tail.push(b' ');
+
+            if arg.is_empty() || arg.contains(' ') || arg.contains('\t') {
+                tail.push(b'"');
+                tail.extend_from_slice(arg.as_bytes());
+                tail.push(b'"');
+            } else {
+                tail.extend_from_slice(arg.as_bytes());
+            }
+        }
+
+        let length = tail.len() - 1;
+        if length > COMMAND_TAIL_MAX {
+            return Err(ErrorCode::InvalidParameter);
+        }
+
+        tail[0] = length as u8;
+        tail.push(b'\r');
+
+        Ok(tail)
+    }
+
+    /// Build an environment block if the environment was changed, with room
+    /// in front to move it onto a paragraph boundary
+    fn environment_block(&self) -> Option<Vec<u8>> {
+        let environment = self.environment.as_ref()?;
+        let mut strings = Vec::new();
+
+        for (key, value) in environment {
+            strings.extend_from_slice(key.as_bytes());
+            strings.push(b'=');
+            strings.extend_from_slice(value.as_bytes());
+            strings.push(0);
+        }
+
+        // An empty environment still needs its terminating empty string
+        if environment.is_empty() {
+            strings.push(0);
+        }
+        strings.push(0);
+
+        // Reserve everything up front so the block can't move after padding
+        let mut block = Vec::with_capacity(strings.len() + 15);
+        block.resize((16 - block.as_ptr() as usize % 16) % 16, 0);
+        block.extend_from_slice(&strings);
+
+        Some(block)
+    }
+}
+
+/// Point a standard handle at another file and hand back a copy of what it
+/// pointed at before
+fn redirect(target: u16, source: u16) -> Result<File, ErrorCode> {
+    let old = ManuallyDrop::new(File::from_handle(target)).try_clone()?;
+    let source = ManuallyDrop::new(File::from_handle(source));
+
+    source.duplicate_onto(target)?;
+
+    Ok(old)
+}
+
+/// Load and execute a program (0x4B00)
+fn exec(program: &str, parameters: &ExecParameterBlock) -> Result<(), ErrorCode> {
+    let error_result: u8;
+    let error_code: u16;
+
+    let program = FarPtr::from_ptr(program.as_ptr());
+    let parameters = FarPtr::from_ptr(parameters as *const ExecParameterBlock);
+
+    unsafe {
+        asm!(
+            "push ebp",         // Nothing survives EXEC on DOS 2, so keep
+            "push esi",         // what the compiler relies on
+            "push ds",
+            "push es",
+            "mov word ptr cs:[{stack_pointer}], sp",
+
+            "mov es, di",       // ES:BX -> parameter block
+            "mov ds, cx",       // DS:DX -> program name
+
+            "mov ax, 0x4b00",
+            "int 0x21",
+
+            "mov cx, cs",       // Get our stack back. SS = CS, for EXEs too
+            "cli",
+            "mov ss, cx",
+            "mov sp, word ptr cs:[{stack_pointer}]",
+            "sti",
+
+            "pop es",
+            "pop ds",
+            "pop esi",
+            "pop ebp",
+            "setc bl",
+
+            stack_pointer = sym EXEC_STACK_POINTER,
+            inout("cx") program.segment => _,
+            inout("dx") program.offset => _,
+            inout("di") parameters.segment => _,
+            in("bx") parameters.offset,
+            lateout("ax") error_code,
+            lateout("bl") error_result,
+        );
+    }
+
+    if error_result != 0 {
+        return Err(ErrorCode::from_u8(error_code as u8).unwrap_or(ErrorCode::UnknownError));
+    }
+
+    Ok(())
+}
@@ -1,7 +1,7 @@
 use rust_dos::*;
 use dos::*;
 
-#[allow(dead_code)]
+test_case!(allocator_test);
 pub(crate) fn allocator_test() {
     let mut box1 = Box::new(5);
     assert_eq!(*box1, 5);
@@ -39,4 +39,55 @@ pub(crate) fn allocator_test() {
     for i in 0..10 {
         assert_eq!(vec1[i], 12);
     }
-}
\ No newline at end of file
+}
+
+test_case!(allocator_alignment_test);
+pub(crate) fn allocator_alignment_test() {
+    use alloc::alloc::{alloc, dealloc, Layout};
+
+    #[repr(align(16))]
+    struct Paragraph([u8; 16]);
+
+    let paragraph = Box::new(Paragraph([7; 16]));
+    assert_eq!(&*paragraph as *const Paragraph as usize % 16, 0);
+    assert_eq!(paragraph.0[15], 7);
+
+    let mut allocations = Vec::new();
+    for i in 0..64 {
+        let align = 1 << (i % 9);
+        let size = 1 + (i * 37) % 300;
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { alloc(layout) };
+
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0, "{} byte allocation not aligned to {}", size, align);
+        unsafe { ptr.write_bytes(i as u8, size) };
+        allocations.push((ptr, layout, i as u8));
+    }
+
+    // Free every other allocation first so the rest land in the gaps
+    for (ptr, layout, value) in allocations.iter().step_by(2) {
+        for offset in 0..layout.size() {
+            assert_eq!(unsafe { *ptr.add(offset) }, *value);
+        }
+        unsafe { dealloc(*ptr, *layout) };
+    }
+
+    for align in [8, 16, 32, 64, 128, 256] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        let ptr = unsafe { alloc(layout) };
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { dealloc(ptr, layout) };
+    }
+
+    for (ptr, layout, value) in allocations.iter().skip(1).step_by(2) {
+        for offset in 0..layout.size() {
+            assert_eq!(unsafe { *ptr.add(offset) }, *value);
+        }
+        unsafe { dealloc(*ptr, *layout) };
+    }
+
+    assert_eq!(allocator::verify_heap(), Ok(()));
+    let stats = allocator::heap_stats();
+    assert_eq!(stats.used + stats.free, stats.total);
+}
@@ -3,8 +3,9 @@ use rust_dos::dos::{
     console,
     datetime
 };
+use rust_dos::test_case;
 
-#[allow(dead_code)]
+test_case!(print_test);
 pub(crate) fn print_test() {
     let date = datetime::Date::now();
     let output = format!("Test print date with print(): {:?}\n$", date);
@@ -1,6 +1,6 @@
 use rust_dos::{*, dos::datetime::{Date, Time}};
 
-#[allow(dead_code)]
+test_case!(datetime_test);
 pub(crate) fn datetime_test() {
     let date = Date::now();
     let time = Time::now();
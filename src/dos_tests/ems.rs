@@ -0,0 +1,40 @@
+use rust_dos::*;
+use rust_dos::software::ems::{Ems, EmsError, PAGE_SIZE};
+
+test_case!(ems_test);
+pub(crate) fn ems_test() {
+    let ems = match Ems::detect() {
+        Some(ems) => ems,
+        None => {
+            println!("No expanded memory manager, skipping");
+            return;
+        },
+    };
+
+    println!("EMS version {:?}, page frame at {:04X}, pages: {:?}",
+        ems.version(), ems.page_frame_segment(), ems.free_pages());
+
+    let mut handle = ems.allocate(2).unwrap();
+    assert_eq!(handle.pages(), Ok(2));
+
+    // Write a different pattern into each logical page through the same window
+    let window = ems.window(0).unwrap();
+    for page in 0..2 {
+        handle.map(0, page).unwrap();
+        unsafe { window.write_bytes(0x40 + page as u8, PAGE_SIZE) };
+    }
+
+    handle.map(1, 0).unwrap();
+    let other_window = ems.window(1).unwrap();
+    assert_eq!(unsafe { *other_window.add(PAGE_SIZE - 1) }, 0x40);
+    assert_eq!(unsafe { *window }, 0x41);
+
+    assert_eq!(handle.map(0, 2), Err(EmsError::LogicalPageOutOfRange));
+    assert!(ems.window(4).is_none());
+
+    if handle.resize(3).is_ok() {
+        handle.map(0, 2).unwrap();
+    }
+    handle.unmap(0).ok();
+    handle.unmap(1).ok();
+}
@@ -0,0 +1,19 @@
+use rust_dos::*;
+use rust_dos::dos::env;
+
+test_case!(args_test);
+pub(crate) fn args_test() {
+    for (index, argument) in env::args().enumerate() {
+        println!("Argument {}: {}", index, argument);
+    }
+}
+
+test_case!(environment_test);
+pub(crate) fn environment_test() {
+    println!("COMSPEC={:?}", env::var("comspec"));
+    println!("Program path: {:?}", env::program_path());
+
+    for (key, value) in env::vars() {
+        println!("{}={}", key, value);
+    }
+}
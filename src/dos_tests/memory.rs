@@ -0,0 +1,34 @@
+use rust_dos::*;
+use rust_dos::dos::memory::{self, MemoryBlock};
+
+test_case!(memory_test);
+pub(crate) fn memory_test() {
+    let available = memory::largest_available();
+    println!("Largest free memory block: {} paragraphs", available);
+    assert!(available > 0x100);
+
+    let mut block = MemoryBlock::allocate(0x100).unwrap();
+    assert_eq!(block.len(), 0x1000);
+
+    let slice = unsafe { block.as_mut_slice() }.unwrap();
+    slice.fill(0x5A);
+    assert!(slice.iter().all(|&byte| byte == 0x5A));
+
+    block.resize(0x80).unwrap();
+    assert_eq!(block.paragraphs(), 0x80);
+    assert!(memory::largest_available() >= available - 0x80);
+
+    let segment = block.into_segment();
+    memory::free(segment).unwrap();
+    assert!(memory::free(segment).is_err());
+}
+
+test_case!(memory_limits_test);
+pub(crate) fn memory_limits_test() {
+    let limits = memory::memory_limits();
+    println!("Memory limits: {:?}, usable up to {:04X}", limits, limits.top());
+
+    assert!(limits.top() <= 0xA000);
+    assert!(limits.top() > dos::misc::data_segment());
+    assert!(limits.program_top >= dos::misc::data_segment() + memory::program_paragraphs());
+}
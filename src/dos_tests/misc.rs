@@ -1,9 +1,71 @@
 use rust_dos::*;
+use rust_dos::dos::error_code::ErrorCode;
+use rust_dos::dos::far_ptr::FarPtr;
+use rust_dos::dos::interrupt::{self, Registers};
 use rust_dos::dos::misc;
+use rust_dos::software::mouse::Mouse;
 
-#[allow(dead_code)]
+test_case!(misc_test);
 pub(crate) fn misc_test() {
     let version = misc::dos_version();
 
     println!("DOS version: {:?}", version);
-}
\ No newline at end of file
+}
+
+test_case!(far_ptr_test);
+pub(crate) fn far_ptr_test() {
+    let mut value: u16 = 0x1234;
+    let ptr = FarPtr::from_ptr(&value as *const u16);
+
+    assert_eq!(ptr.segment, misc::data_segment() + (&value as *const u16 as u32 / 16) as u16);
+    assert_eq!(ptr.to_ptr::<u16>(), Some(&mut value as *mut u16));
+    assert_eq!(ptr.read_word(), 0x1234);
+
+    unsafe { ptr.write_word(0xBEEF) };
+    assert_eq!(unsafe { (&value as *const u16).read_volatile() }, 0xBEEF);
+    assert_eq!(ptr.add(1).read_byte(), 0xBE);
+
+    // The PSP starts with INT 20h (CD 20)
+    assert_eq!(FarPtr::new(misc::data_segment(), 0).read_word(), 0x20CD);
+
+    println!("Far pointer to {:p}: {}", &value, ptr);
+}
+
+test_case!(interrupt_test);
+pub(crate) fn interrupt_test() {
+    let version = misc::dos_version();
+    let data_segment = misc::data_segment();
+
+    let mut registers = Registers::new();
+    registers.ax = 0x3000;
+    unsafe { interrupt::intdos(&mut registers) }.unwrap();
+
+    assert_eq!(registers.al(), version.major());
+    assert_eq!(registers.ah(), version.minor());
+    assert_eq!(misc::data_segment(), data_segment);
+
+    // Close a handle that can't be open (3Eh)
+    let mut registers = Registers::new();
+    registers.ax = 0x3e00;
+    registers.bx = 0xFFFF;
+
+    assert_eq!(unsafe { interrupt::intdos(&mut registers) }, Err(ErrorCode::InvalidHandle));
+    assert!(registers.carry());
+
+    // Get the DTA (2Fh), which is returned in ES:BX and starts out in the PSP
+    let mut registers = Registers::new();
+    registers.ax = 0x2f00;
+    registers.es = 0;
+    unsafe { interrupt::int86(0x21, &mut registers) }.unwrap();
+
+    assert_eq!(registers.es_bx(), FarPtr::new(data_segment, 0x80));
+
+    println!("int86 returned DOS {}.{:0>2}", version.major(), version.minor());
+}
+
+test_case!(mouse_test);
+pub(crate) fn mouse_test() {
+    // No driver is fine, it just has to answer
+    let result = Mouse::initialize();
+    println!("Mouse mode: {:?}", result);
+}
@@ -4,3 +4,9 @@ pub(crate) mod allocator_test;
 pub(crate) mod datetime;
 pub(crate) mod console;
 pub(crate) mod misc;
+pub(crate) mod env;
+pub(crate) mod memory;
+pub(crate) mod process;
+pub(crate) mod xms;
+pub(crate) mod ems;
+pub(crate) mod video;
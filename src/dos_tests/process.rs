@@ -0,0 +1,25 @@
+use rust_dos::*;
+use rust_dos::dos::{
+    file::{File, FileAttributes},
+    process::Command
+};
+
+test_case!(process_test);
+pub(crate) fn process_test() {
+    let status = Command::shell("VER").status();
+    println!("VER exited with {:?}", status);
+
+    let output = File::create("EXEC.TMP\0", FileAttributes::NORMAL).unwrap();
+    let status = Command::shell("ECHO hello from a child")
+        .env("RUSTDOS", "1")
+        .stdout(&output)
+        .status()
+        .unwrap();
+    output.close().unwrap();
+    assert!(status.success());
+
+    let missing = Command::new("C:\\NOTHERE.COM").status();
+    println!("Missing program: {:?}", missing.err());
+
+    File::delete("EXEC.TMP\0").unwrap();
+}
@@ -0,0 +1,19 @@
+use rust_dos::*;
+use rust_dos::bios::video::{self, VesaMode, VideoMode};
+
+test_case!(video_test);
+pub(crate) fn video_test() {
+    // Set resolution to 800x600x8
+    let mode = VesaMode::new(0x103,
+        false,
+        true,
+        false);
+
+    let result = video::set_video_vesa(mode);
+
+    // Back to text mode so the rest of the output can be read
+    video::set_video(VideoMode::Text80_25C);
+
+    println!("VESA mode 103h: {:?}", result);
+    assert!(result.is_ok());
+}
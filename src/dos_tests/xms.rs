@@ -0,0 +1,65 @@
+use rust_dos::*;
+use rust_dos::dos::Vec;
+use rust_dos::software::xms::{Xms, XmsBuffer, XmsError};
+
+test_case!(xms_test);
+pub(crate) fn xms_test() {
+    let xms = match Xms::detect() {
+        Some(xms) => xms,
+        None => {
+            println!("No XMS driver, skipping");
+            return;
+        },
+    };
+
+    let version = xms.version();
+    println!("XMS version {:x}.{:02x}, free memory: {:?}", version.major(), version.minor(), xms.free_memory());
+
+    let mut block = xms.allocate(4).unwrap();
+    assert_eq!(block.info().unwrap().size, 4);
+    block.resize(8).unwrap();
+    assert_eq!(block.size().unwrap(), 8 * 1024);
+    {
+        let locked = block.lock().unwrap();
+        assert!(locked.address() >= 0x100000);
+    }
+    assert_eq!(block.info().unwrap().lock_count, 0);
+
+    let data: Vec<u8> = (0..=254).collect();
+    let mut buffer = XmsBuffer::from_slice(xms, &data).unwrap();
+    assert_eq!(buffer.to_vec(0, data.len()).unwrap(), data);
+
+    // Odd offsets and lengths don't disturb the neighbouring bytes
+    buffer.write(3, b"abc").unwrap();
+    let mut window = [0; 7];
+    buffer.read(1, &mut window).unwrap();
+    assert_eq!(&window, &[1, 2, b'a', b'b', b'c', 6, 7]);
+
+    assert_eq!(buffer.write(254, b"xy"), Err(XmsError::InvalidDestinationOffset));
+    assert_eq!(buffer.read(255, &mut [0]), Err(XmsError::InvalidSourceOffset));
+}
+
+test_case!(xms_buffer_end_test);
+pub(crate) fn xms_buffer_end_test() {
+    let xms = match Xms::detect() {
+        Some(xms) => xms,
+        None => {
+            println!("No XMS driver, skipping");
+            return;
+        },
+    };
+
+    // The odd byte at the very end of a whole kilobyte is moved as a word
+    let mut buffer = XmsBuffer::new(xms, 1024).unwrap();
+    buffer.write(1022, &[1, 2]).unwrap();
+    buffer.write(1023, &[0xAB]).unwrap();
+
+    let mut last = [0; 1];
+    buffer.read(1023, &mut last).unwrap();
+    assert_eq!(last, [0xAB]);
+    assert_eq!(buffer.to_vec(1022, 2).unwrap(), [1, 0xAB]);
+
+    let data: Vec<u8> = (0..1023).map(|index| index as u8).collect();
+    let buffer = XmsBuffer::from_slice(xms, &data).unwrap();
+    assert_eq!(buffer.to_vec(0, data.len()).unwrap(), data);
+}
@@ -0,0 +1,490 @@
+//! Byte streams, like `std::io`
+//! ======================================================================
+//!
+//! [Read], [Write] and [Seek] let code work with any source or destination
+//! of bytes: [File](crate::dos::file::File), byte slices and `Vec<u8>`, or
+//! anything else that implements them. Errors are DOS [ErrorCode]s.
+//!
+//! Every read or write on a file is a trip through INT 21h, so code that
+//! works a few bytes at a time should go through a [BufReader] or
+//! [BufWriter]. [BufRead] adds reading text line by line.
+//!
+//! ```
+//! use rust_dos::io::{self, Read, Write};
+//!
+//! let mut input = File::open("CONFIG.SYS\0", AccessMode::default())?;
+//! let mut output = File::create("CONFIG.BAK\0", FileAttributes::NORMAL)?;
+//! io::copy(&mut input, &mut output)?;
+//!
+//! let mut header = [0; 2];
+//! File::open("PROGRAM.EXE\0", AccessMode::default())?.read_exact(&mut header)?;
+//! write!(output, "REM header {:?}\r\n", header)?;
+//!
+//! for line in BufReader::new(File::open("AUTOEXEC.BAT\0", AccessMode::default())?).lines() {
+//!     println!("{}", line?);
+//! }
+//! ```
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::dos::error_code::ErrorCode;
+
+mod buffered;
+
+pub use buffered::{BufReader, BufWriter};
+
+/// How much [Read::read_to_end] asks for at a time
+const READ_CHUNK_SIZE: usize = 0x1000;
+/// Size of the buffer [copy] goes through
+const COPY_BUFFER_SIZE: usize = 0x1000;
+
+/// Where to seek to. See [Seek::seek]
+///
+/// DOS treats the offset as signed for `End` and `Current`, so
+/// `Current(-2i32 as u32)` moves back two bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeekFrom {
+    Start(u32),
+    End(u32),
+    Current(u32),
+}
+
+/// Something bytes can be read from
+pub trait Read {
+    /// Read up to `buffer.len()` bytes and return how many were read. 0 means
+    /// the end has been reached, or that `buffer` is empty
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode>;
+
+    /// Fill all of `buffer`. Fails with [ErrorCode::CannotCompleteFileOperation]
+    /// if the end comes first, in which case it's unspecified how much of
+    /// `buffer` was filled
+    fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<(), ErrorCode> {
+        while !buffer.is_empty() {
+            match self.read(buffer)? {
+                0 => return Err(ErrorCode::CannotCompleteFileOperation),
+                read => buffer = &mut buffer[read..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read everything up to the end and append it to `buffer`. Returns the
+    /// number of bytes read
+    fn read_to_end(&mut self, buffer: &mut Vec<u8>) -> Result<usize, ErrorCode> {
+        let start = buffer.len();
+
+        loop {
+            let length = buffer.len();
+            buffer.resize(length + READ_CHUNK_SIZE, 0);
+
+            match self.read(&mut buffer[length..]) {
+                Ok(0) => {
+                    buffer.truncate(length);
+                    return Ok(length - start);
+                },
+                Ok(read) => buffer.truncate(length + read),
+                Err(error) => {
+                    buffer.truncate(length);
+                    return Err(error);
+                },
+            }
+        }
+    }
+
+    /// Read everything up to the end and append it to `buffer`. Fails with
+    /// [ErrorCode::InvalidData], leaving `buffer` as it was, if it isn't
+    /// UTF-8
+    fn read_to_string(&mut self, buffer: &mut String) -> Result<usize, ErrorCode> {
+        let mut bytes = Vec::new();
+        self.read_to_end(&mut bytes)?;
+
+        let text = core::str::from_utf8(&bytes).map_err(|_| ErrorCode::InvalidData)?;
+        buffer.push_str(text);
+
+        Ok(bytes.len())
+    }
+
+    /// Borrow the reader, so it can be passed somewhere that takes a reader
+    /// by value and still be used afterwards
+    fn by_ref(&mut self) -> &mut Self
+        where Self: Sized {
+        self
+    }
+}
+
+/// Something bytes can be written to
+pub trait Write {
+    /// Write some of `buffer` and return how many bytes were written. Less
+    /// than asked for usually means the disk is full
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode>;
+
+    /// Make sure everything written so far has reached its destination
+    fn flush(&mut self) -> Result<(), ErrorCode>;
+
+    /// Write all of `buffer`. Fails with [ErrorCode::InsufficientDiskSpace]
+    /// if nothing more can be written
+    fn write_all(&mut self, mut buffer: &[u8]) -> Result<(), ErrorCode> {
+        while !buffer.is_empty() {
+            match self.write(buffer)? {
+                0 => return Err(ErrorCode::InsufficientDiskSpace),
+                written => buffer = &buffer[written..],
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write formatted text, which is what `write!` calls
+    fn write_fmt(&mut self, args: fmt::Arguments) -> Result<(), ErrorCode> {
+        // fmt::Error can't carry the DOS error, so it's kept on the side
+        struct Adapter<'a, W: Write + ?Sized> {
+            inner: &'a mut W,
+            error: Option<ErrorCode>,
+        }
+
+        impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.inner.write_all(s.as_bytes()).map_err(|error| {
+                    self.error = Some(error);
+                    fmt::Error
+                })
+            }
+        }
+
+        let mut adapter = Adapter {
+            inner: self,
+            error: None,
+        };
+
+        fmt::write(&mut adapter, args).map_err(|_| adapter.error.unwrap_or(ErrorCode::UnknownError))
+    }
+
+    /// Borrow the writer. See [Read::by_ref]
+    fn by_ref(&mut self) -> &mut Self
+        where Self: Sized {
+        self
+    }
+}
+
+/// A reader with a buffer of its own, which can be looked into before
+/// deciding how much of it to take
+pub trait BufRead: Read {
+    /// The buffered bytes, filled from the source first if there are none.
+    /// Empty at the end
+    fn fill_buf(&mut self) -> Result<&[u8], ErrorCode>;
+
+    /// Mark `count` bytes from [BufRead::fill_buf] as read
+    fn consume(&mut self, count: usize);
+
+    /// Read up to and including `delimiter`, or to the end, and append it to
+    /// `buffer`. Returns the number of bytes read, 0 at the end
+    fn read_until(&mut self, delimiter: u8, buffer: &mut Vec<u8>) -> Result<usize, ErrorCode> {
+        let mut read = 0;
+
+        loop {
+            let (done, used) = {
+                let available = self.fill_buf()?;
+
+                match available.iter().position(|&byte| byte == delimiter) {
+                    Some(index) => {
+                        buffer.extend_from_slice(&available[..=index]);
+                        (true, index + 1)
+                    },
+                    None => {
+                        buffer.extend_from_slice(available);
+                        (available.is_empty(), available.len())
+                    },
+                }
+            };
+
+            self.consume(used);
+            read += used;
+
+            if done {
+                return Ok(read);
+            }
+        }
+    }
+
+    /// Read a line and append it to `line`, line ending included. Returns
+    /// the number of bytes read, 0 at the end. Fails with
+    /// [ErrorCode::InvalidData], leaving `line` as it was, if the line isn't
+    /// UTF-8
+    fn read_line(&mut self, line: &mut String) -> Result<usize, ErrorCode> {
+        let mut bytes = Vec::new();
+        let read = self.read_until(b'\n', &mut bytes)?;
+
+        let text = core::str::from_utf8(&bytes).map_err(|_| ErrorCode::InvalidData)?;
+        line.push_str(text);
+
+        Ok(read)
+    }
+
+    /// Iterator over the lines, without their CR LF or LF endings
+    fn lines(self) -> Lines<Self>
+        where Self: Sized {
+        Lines {
+            inner: self,
+        }
+    }
+}
+
+/// Iterator over the lines of a [BufRead]. See [BufRead::lines]
+#[derive(Debug)]
+pub struct Lines<B> {
+    inner: B,
+}
+
+impl<B: BufRead> Iterator for Lines<B> {
+    type Item = Result<String, ErrorCode>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+
+        match self.inner.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+
+                Some(Ok(line))
+            },
+            Err(error) => Some(Err(error)),
+        }
+    }
+}
+
+/// Something with a position that can be moved
+pub trait Seek {
+    /// Move to `position` and return the new offset from the start
+    fn seek(&mut self, position: SeekFrom) -> Result<u32, ErrorCode>;
+
+    /// Go back to the start
+    fn rewind(&mut self) -> Result<(), ErrorCode> {
+        self.seek(SeekFrom::Start(0))?;
+
+        Ok(())
+    }
+
+    /// Current offset from the start
+    fn stream_position(&mut self) -> Result<u32, ErrorCode> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
+
+/// Copy everything from `reader` to `writer` and return the number of bytes
+/// copied
+pub fn copy<R, W>(reader: &mut R, writer: &mut W) -> Result<u64, ErrorCode>
+    where R: Read + ?Sized, W: Write + ?Sized {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(copied);
+        }
+
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+    }
+}
+
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        (**self).read(buffer)
+    }
+}
+
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+        (**self).write(buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorCode> {
+        (**self).flush()
+    }
+}
+
+impl<B: BufRead + ?Sized> BufRead for &mut B {
+    fn fill_buf(&mut self) -> Result<&[u8], ErrorCode> {
+        (**self).fill_buf()
+    }
+
+    fn consume(&mut self, count: usize) {
+        (**self).consume(count)
+    }
+}
+
+impl<S: Seek + ?Sized> Seek for &mut S {
+    fn seek(&mut self, position: SeekFrom) -> Result<u32, ErrorCode> {
+        (**self).seek(position)
+    }
+}
+
+/// Reading from a slice takes bytes off the front of it
+impl Read for &[u8] {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        let count = buffer.len().min(self.len());
+        let (read, rest) = self.split_at(count);
+
+        buffer[..count].copy_from_slice(read);
+        *self = rest;
+
+        Ok(count)
+    }
+}
+
+impl BufRead for &[u8] {
+    fn fill_buf(&mut self) -> Result<&[u8], ErrorCode> {
+        Ok(self)
+    }
+
+    fn consume(&mut self, count: usize) {
+        *self = &self[count..];
+    }
+}
+
+/// Writing to a slice fills it from the front, and writes nothing once it's
+/// full
+impl Write for &mut [u8] {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+        let count = buffer.len().min(self.len());
+        let (written, rest) = core::mem::take(self).split_at_mut(count);
+
+        written.copy_from_slice(&buffer[..count]);
+        *self = rest;
+
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+/// Writing to a vector appends to it
+impl Write for Vec<u8> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+        self.extend_from_slice(buffer);
+
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hands out at most a few bytes per read, like a slow device
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+            let count = buffer.len().min(self.step);
+            (&mut self.data).read(&mut buffer[..count])
+        }
+    }
+
+    #[test]
+    fn read_exact_across_short_reads() {
+        let mut reader = Trickle { data: b"MZ\x90\x00rest", step: 3 };
+        let mut header = [0; 4];
+
+        reader.read_exact(&mut header).unwrap();
+        assert_eq!(&header, b"MZ\x90\x00");
+
+        let mut rest = [0; 5];
+        assert_eq!(reader.read_exact(&mut rest), Err(ErrorCode::CannotCompleteFileOperation));
+    }
+
+    #[test]
+    fn read_to_end_appends() {
+        let data: Vec<u8> = (0..READ_CHUNK_SIZE * 2 + 10).map(|value| value as u8).collect();
+        let mut reader = Trickle { data: &data, step: 1000 };
+        let mut buffer = vec![0xAA];
+
+        assert_eq!(reader.read_to_end(&mut buffer), Ok(data.len()));
+        assert_eq!(buffer[0], 0xAA);
+        assert_eq!(&buffer[1..], &data[..]);
+    }
+
+    #[test]
+    fn read_to_string_checks_utf8() {
+        let mut text = String::from("> ");
+
+        assert_eq!((&b"ECHO OFF"[..]).read_to_string(&mut text), Ok(8));
+        assert_eq!(text, "> ECHO OFF");
+        assert_eq!((&b"\xff\xfe"[..]).read_to_string(&mut text), Err(ErrorCode::InvalidData));
+        assert_eq!(text, "> ECHO OFF");
+    }
+
+    #[test]
+    fn lines_strip_line_endings() {
+        let text = b"@ECHO OFF\r\nPATH C:\\DOS\n\r\nSET TEMP=C:\\TMP";
+        let lines: Vec<String> = (&text[..]).lines().map(Result::unwrap).collect();
+
+        assert_eq!(lines, ["@ECHO OFF", "PATH C:\\DOS", "", "SET TEMP=C:\\TMP"]);
+    }
+
+    #[test]
+    fn read_line_keeps_line_ending() {
+        let mut reader = &b"FILES=30\r\nBUFFERS=20"[..];
+        let mut line = String::new();
+
+        assert_eq!(reader.read_line(&mut line), Ok(10));
+        assert_eq!(line, "FILES=30\r\n");
+
+        line.clear();
+        assert_eq!(reader.read_line(&mut line), Ok(10));
+        assert_eq!(line, "BUFFERS=20");
+        assert_eq!(reader.read_line(&mut line), Ok(0));
+    }
+
+    #[test]
+    fn write_all_stops_when_full() {
+        let mut storage = [0; 4];
+        let mut writer = &mut storage[..];
+
+        assert_eq!(writer.write_all(b"AB"), Ok(()));
+        assert_eq!(writer.write_all(b"CDE"), Err(ErrorCode::InsufficientDiskSpace));
+        assert_eq!(&storage, b"ABCD");
+    }
+
+    #[test]
+    fn write_fmt_formats() {
+        let mut output = Vec::new();
+
+        write!(output, "{} files, {:04X}h", 3, 0x2c).unwrap();
+        assert_eq!(output, b"3 files, 002Ch");
+
+        let mut storage = [0; 4];
+        assert_eq!(write!(&mut storage[..], "{}", 123456), Err(ErrorCode::InsufficientDiskSpace));
+    }
+
+    #[test]
+    fn copy_moves_everything() {
+        let data: Vec<u8> = (0..COPY_BUFFER_SIZE * 3).map(|value| (value * 7) as u8).collect();
+        let mut reader = Trickle { data: &data, step: 700 };
+        let mut output = Vec::new();
+
+        assert_eq!(copy(&mut reader, &mut output), Ok(data.len() as u64));
+        assert_eq!(output, data);
+    }
+}
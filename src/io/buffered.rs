@@ -0,0 +1,351 @@
+//! Buffering for readers and writers, so that small reads and writes don't
+//! each cost a call to DOS
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::{self, ManuallyDrop};
+use core::ptr;
+
+use crate::dos::error_code::ErrorCode;
+use crate::io::{BufRead, Read, Seek, SeekFrom, Write};
+
+/// Buffer size used by [BufReader::new] and [BufWriter::new]. Two disk
+/// sectors, a handful of bytes of the program's memory for a lot fewer
+/// INT 21h calls
+pub const DEFAULT_BUFFER_SIZE: usize = 0x400;
+
+/// Reads from `R` in blocks and hands the bytes out from memory
+///
+/// ```
+/// let mut config = BufReader::new(File::open("CONFIG.SYS\0", AccessMode::default())?);
+/// let mut line = String::new();
+/// config.read_line(&mut line)?;
+/// ```
+pub struct BufReader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    /// Next byte of `buffer` to hand out
+    position: usize,
+    /// How much of `buffer` holds data
+    filled: usize,
+}
+
+impl<R: Read> BufReader<R> {
+    /// Buffer `inner` with a buffer of [DEFAULT_BUFFER_SIZE] bytes
+    pub fn new(inner: R) -> Self {
+        Self::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    /// Buffer `inner` with a buffer of `capacity` bytes
+    pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        Self {
+            inner,
+            buffer: vec![0; capacity],
+            position: 0,
+            filled: 0,
+        }
+    }
+}
+
+impl<R> BufReader<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly skips whatever is buffered
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// The bytes read from the inner reader but not handed out yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.position..self.filled]
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Take back the inner reader. Anything still buffered is lost
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn discard_buffer(&mut self) {
+        self.position = 0;
+        self.filled = 0;
+    }
+}
+
+impl<R: Read> Read for BufReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+        // Nothing is gained by copying big reads through the buffer
+        if self.position == self.filled && buffer.len() >= self.capacity() {
+            self.discard_buffer();
+            return self.inner.read(buffer);
+        }
+
+        let read = self.fill_buf()?.read(buffer)?;
+        self.consume(read);
+
+        Ok(read)
+    }
+}
+
+impl<R: Read> BufRead for BufReader<R> {
+    fn fill_buf(&mut self) -> Result<&[u8], ErrorCode> {
+        if self.position == self.filled {
+            self.filled = self.inner.read(&mut self.buffer)?;
+            self.position = 0;
+        }
+
+        Ok(&self.buffer[self.position..self.filled])
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.position = (self.position + count).min(self.filled);
+    }
+}
+
+/// Seeking throws away the buffer. `SeekFrom::Current` is relative to what
+/// has been read from the `BufReader`, not from the inner reader
+impl<R: Seek> Seek for BufReader<R> {
+    fn seek(&mut self, position: SeekFrom) -> Result<u32, ErrorCode> {
+        let position = match position {
+            SeekFrom::Current(offset) => {
+                let buffered = (self.filled - self.position) as u32;
+                SeekFrom::Current(offset.wrapping_sub(buffered))
+            },
+            position => position,
+        };
+
+        self.discard_buffer();
+        self.inner.seek(position)
+    }
+}
+
+/// Collects writes in memory and passes them on to `W` in blocks
+///
+/// What's still buffered is written when the `BufWriter` is dropped, but an
+/// error then can't be reported. Call [Write::flush] or
+/// [BufWriter::into_inner] to find out about it.
+///
+/// ```
+/// let mut log = BufWriter::new(File::create("DEBUG.LOG\0", FileAttributes::NORMAL)?);
+/// for line in lines {
+///     write!(log, "{}\r\n", line)?;
+/// }
+/// log.flush()?;
+/// ```
+pub struct BufWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> BufWriter<W> {
+    /// Buffer `inner` with a buffer of [DEFAULT_BUFFER_SIZE] bytes
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(DEFAULT_BUFFER_SIZE, inner)
+    }
+
+    /// Buffer `inner` with a buffer of `capacity` bytes
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            inner,
+            buffer: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Writing to the inner writer directly puts the bytes ahead of whatever
+    /// is buffered
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The bytes written but not passed on yet
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Write out the buffer and take back the inner writer. If that fails
+    /// the writer is closed along with the `BufWriter`
+    pub fn into_inner(mut self) -> Result<W, ErrorCode> {
+        self.flush_buffer()?;
+
+        // Dropping self would flush again, so take it apart instead
+        let mut this = ManuallyDrop::new(self);
+        drop(mem::take(&mut this.buffer));
+
+        Ok(unsafe { ptr::read(&this.inner) })
+    }
+
+    /// Pass the buffer on to the inner writer. What can't be written stays
+    /// in the buffer
+    fn flush_buffer(&mut self) -> Result<(), ErrorCode> {
+        let mut written = 0;
+
+        let result = loop {
+            if written == self.buffer.len() {
+                break Ok(());
+            }
+
+            match self.inner.write(&self.buffer[written..]) {
+                Ok(0) => break Err(ErrorCode::InsufficientDiskSpace),
+                Ok(count) => written += count,
+                Err(error) => break Err(error),
+            }
+        };
+
+        self.buffer.drain(..written);
+        result
+    }
+}
+
+impl<W: Write> Write for BufWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+        if self.buffer.len() + buffer.len() > self.capacity() {
+            self.flush_buffer()?;
+        }
+
+        // Nothing is gained by copying big writes through the buffer
+        if buffer.len() >= self.capacity() {
+            return self.inner.write(buffer);
+        }
+
+        self.buffer.extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorCode> {
+        self.flush_buffer()?;
+        self.inner.flush()
+    }
+}
+
+/// Seeking writes out the buffer first
+impl<W: Write + Seek> Seek for BufWriter<W> {
+    fn seek(&mut self, position: SeekFrom) -> Result<u32, ErrorCode> {
+        self.flush_buffer()?;
+        self.inner.seek(position)
+    }
+}
+
+impl<W: Write> Drop for BufWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush_buffer();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use core::cell::Cell;
+
+    /// Counts the calls made to it, like INT 21h calls to a file
+    struct Counted<'a, T> {
+        inner: T,
+        calls: &'a Cell<usize>,
+    }
+
+    impl<T: Read> Read for Counted<'_, T> {
+        fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ErrorCode> {
+            self.calls.set(self.calls.get() + 1);
+            self.inner.read(buffer)
+        }
+    }
+
+    impl<T: Write> Write for Counted<'_, T> {
+        fn write(&mut self, buffer: &[u8]) -> Result<usize, ErrorCode> {
+            self.calls.set(self.calls.get() + 1);
+            self.inner.write(buffer)
+        }
+
+        fn flush(&mut self) -> Result<(), ErrorCode> {
+            self.inner.flush()
+        }
+    }
+
+    #[test]
+    fn small_reads_come_from_the_buffer() {
+        let calls = Cell::new(0);
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = BufReader::with_capacity(64, Counted { inner: &data[..], calls: &calls });
+
+        let mut byte = [0];
+        for expected in 0..100 {
+            reader.read_exact(&mut byte).unwrap();
+            assert_eq!(byte[0], expected);
+        }
+
+        assert_eq!(reader.read(&mut byte), Ok(0));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn lines_across_buffer_refills() {
+        let text = b"DEVICE=C:\\DOS\\HIMEM.SYS\r\nDOS=HIGH\r\n\r\nFILES=40";
+        let lines: Vec<String> = BufReader::with_capacity(4, &text[..]).lines().map(Result::unwrap).collect();
+
+        assert_eq!(lines, ["DEVICE=C:\\DOS\\HIMEM.SYS", "DOS=HIGH", "", "FILES=40"]);
+    }
+
+    #[test]
+    fn writes_are_collected() {
+        let calls = Cell::new(0);
+        let mut output = Vec::new();
+        let mut writer = BufWriter::with_capacity(16, Counted { inner: &mut output, calls: &calls });
+
+        for _ in 0..10 {
+            writer.write_all(b"abc").unwrap();
+        }
+        assert_eq!(calls.get(), 1);
+
+        writer.flush().unwrap();
+        assert_eq!(calls.get(), 2);
+        assert!(writer.buffer().is_empty());
+
+        writer.write_all(&[b'x'; 20]).unwrap();
+        assert_eq!(calls.get(), 3);
+
+        drop(writer);
+        assert_eq!(output.len(), 50);
+    }
+
+    #[test]
+    fn drop_flushes() {
+        let mut output = Vec::new();
+        {
+            let mut writer = BufWriter::new(&mut output);
+            write!(writer, "ECHO {}\r\n", 1).unwrap();
+        }
+
+        assert_eq!(output, b"ECHO 1\r\n");
+    }
+
+    #[test]
+    fn into_inner_flushes() {
+        let mut writer = BufWriter::new(Vec::new());
+        writer.write_all(b"PAUSE").unwrap();
+
+        assert_eq!(writer.into_inner().unwrap(), b"PAUSE");
+    }
+
+    #[test]
+    fn unwritten_bytes_stay_buffered() {
+        let mut space = [0; 4];
+        let mut writer = BufWriter::with_capacity(8, &mut space[..]);
+        writer.write_all(b"ABCDEF").unwrap();
+
+        assert_eq!(writer.flush(), Err(ErrorCode::InsufficientDiskSpace));
+        assert_eq!(writer.buffer(), b"EF");
+    }
+}
@@ -18,6 +18,7 @@
 //! | 0D    | Disk reset                                                      |
 //! | 0E    | Select default drive                                            |
 //! | 19    | Get current default drive                                       |
+//! | 1A ✓  | [Set disk transfer address](dos::file::Directory::read_dir)     |
 //! | 1B  ⚠️ | Replaced by 36 (Get free disk space)                            |
 //! | 1C  ⚠️ | Replaced by 36 (Get free disk space)                            |
 //! | 25    | Set interrupt vector                                            |
@@ -26,6 +27,7 @@
 //! | 2C ✓  | Get system time                                                 |
 //! | 2D ✓  | Set system time                                                 |
 //! | 2E ✓  | [Enable write verification](dos::file::set_verify_writes)       |
+//! | 2F ✓  | [Get disk transfer address](dos::file::Directory::read_dir)     |
 //! | 30 ✓  | [Get DOS version](dos::misc::dos_version)                       |
 //! | 35    | Get interrupt vector                                            |
 //! | 36    | [Get free disk space](dos::file::StorageParameters::disk_space) |
@@ -39,13 +41,21 @@
 //! | 40 ✓  | [Write file handle](dos::file::File::write)                     |
 //! | 41    | [Delete file](dos::file::File::delete)                          |
 //! | 42 ✓  | [Seek file](dos::file::File::close)                             |
-//! | 43 ~  | [Get/set file attributes](dos::file::File::attributes)          |
+//! | 43 ✓  | [Get/set file attributes](dos::file::File::set_attributes)      |
+//! | 45 ✓  | [Duplicate file handle](dos::file::File::try_clone)             |
+//! | 46 ✓  | [Force duplicate file handle](dos::file::File::duplicate_onto)  |
 //! | 47    | Get current directory                                           |
+//! | 48 ✓  | [Allocate memory](dos::memory::MemoryBlock::allocate)           |
+//! | 49 ✓  | [Free memory](dos::memory::free)                                |
+//! | 4A ✓  | [Resize memory block](dos::memory::MemoryBlock::resize)         |
+//! | 4B ~  | [Execute program](dos::process::Command)                        |
 //! | 4C ✓  | Exit program                                                    |
-//! | 4D    | Get return code                                                 |
+//! | 4D ✓  | [Get return code](dos::process::exit_status)                    |
+//! | 4E ✓  | [Find first file](dos::file::Directory::read_dir)               |
+//! | 4F ✓  | [Find next file](dos::file::Directory::read_dir)                |
 //! | 54    | [Check file verification status](dos::file::verify_writes)      |
-//! | 56    | Rename file                                                     |
-//! | 57 ~  | Get/set file date                                               |
+//! | 56 ✓  | [Rename file](dos::file::File::rename)                          |
+//! | 57 ✓  | [Get/set file date](dos::file::File::set_last_write)            |
 //! 
 //! Legend:
 //! * ✓ = All features implemented
@@ -58,19 +68,74 @@
 //! * [MS-DOS Version 4.0 Programmer's Reference](https://www.pcjs.org/documents/books/mspl13/msdos/dosref40/)
 //! 
 
-#![no_std]
-#![feature(alloc_error_handler)]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), feature(alloc_error_handler))]
+
+// Only the parts that don't talk to DOS are built for `cargo test` on the host
 
 #[macro_use]
 pub mod dos;
+#[cfg(not(test))]
 pub mod bios;
+#[cfg(not(test))]
 pub mod dpkey;
+pub mod io;
+#[cfg(not(test))]
 pub mod software;
+#[cfg(not(test))]
+pub mod testing;
+#[cfg(not(test))]
 extern crate rlibc;
 extern crate alloc;
 
+#[cfg(not(test))]
 use crate::dos::allocator::GLOBAL_ALLOCATOR;
 
+// Entry point of an EXE program (exe.x), link.x leaves it out. DOS starts it
+// with DS and ES pointing at the PSP, CS at the image, 10h paragraphs further
+// on, and SS:SP at the stack segment after .bss. The image is then moved up
+// to where it's linked, past the program's stack. After this everything is
+// the same as for a COM program, so the rest of the crate works with both
+#[cfg(not(test))]
+core::arch::global_asm!(
+    ".section .exe_startup, \"ax\"",
+    ".global _exe_start",
+    "_exe_start:",
+    ".byte 0xea",                       // Far jump to the next instruction,
+    ".word 2f",                         // with CS set to the PSP. DOS adds
+    "3:",                               // the image segment to -10h on load
+    ".word 0xfff0",
+    "2:",
+    "mov ax, ds",
+    "mov es, ax",
+    "mov esi, offset _load_end - 1",    // Move the image up by the size of
+    "mov edi, offset _image_end - 1",   // the stack. Backwards, since the
+    "mov ecx, offset _move_size",       // two overlap
+    "std",
+    "rep movsb byte ptr es:[edi], byte ptr [esi]",
+    "cld",
+
+    "mov edi, offset _bss_start",       // .bss isn't in the file and DOS
+    "4:",                               // doesn't clear memory
+    "cmp edi, offset _bss_end",
+    "jae 5f",
+    "mov byte ptr [edi], 0",
+    "inc edi",
+    "jmp 4b",
+    "5:",
+    "mov ax, ds",
+    "cli",
+    "mov ss, ax",                       // Pointers to locals are DS relative
+    "mov esp, offset _stack_top",
+    "sti",
+    "jmp _start",
+
+    ".section .exe_relocations, \"a\"", // Offset and segment of the word to
+    ".word 3b - 0x100",                 // fix up, relative to the image
+    ".word 0",
+);
+
+#[cfg(not(test))]
 #[link_section = ".startup"]
 #[no_mangle]
 fn _start() -> ! {
@@ -78,22 +143,72 @@ fn _start() -> ! {
         GLOBAL_ALLOCATOR.init();
     }
     extern "Rust" {
-        fn main() -> ();
+        fn main() -> u8;
     }
-    unsafe {
-        main();
+    let code = unsafe {
+        main()
+    };
+    dos::exit(code);
+}
+
+/// Exit code used when `main` returns an error
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit code used when the program panics, same as Rust's standard library
+pub const EXIT_PANIC: u8 = 101;
+
+/// Return types `main` may have when used with [entry]. The value is turned
+/// into the exit code that batch files see through `ERRORLEVEL`.
+///
+/// * `()` exits with 0
+/// * `u8` exits with that value
+/// * `Result<(), E>` exits with 0, or prints the error and exits with
+///   [EXIT_FAILURE]
+pub trait Termination {
+    fn report(self) -> u8;
+}
+
+impl Termination for () {
+    fn report(self) -> u8 {
+        0
+    }
+}
+
+impl Termination for u8 {
+    fn report(self) -> u8 {
+        self
+    }
+}
+
+#[cfg(not(test))]
+impl<T: Termination, E: core::fmt::Debug> Termination for Result<T, E> {
+    fn report(self) -> u8 {
+        match self {
+            Ok(value) => value.report(),
+            Err(error) => {
+                println!("Error: {:?}", error);
+                EXIT_FAILURE
+            }
+        }
     }
-    dos::exit(0);
 }
 
+/// Declare the program's entry point. `main` can return anything that
+/// implements [Termination]
+///
+/// ```
+/// entry!(main);
+///
+/// fn main() -> Result<(), ErrorCode> {
+///     let file = File::open("CONFIG.SYS\0", AccessMode::default())?;
+///     Ok(())
+/// }
+/// ```
 #[macro_export]
 macro_rules! entry {
     ($path:path) => {
         #[export_name = "main"]
-        pub fn __main() -> () {
-            // type check the given path
-            let f: fn() -> () = $path;
-            f()
+        pub fn __main() -> u8 {
+            $crate::Termination::report($path())
         }
     };
 }
\ No newline at end of file
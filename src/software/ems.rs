@@ -0,0 +1,289 @@
+//! Expanded Memory Specification (EMS) Interrupt Service Routines
+//! ======================================================================
+//!
+//! An expanded memory manager (EMM) such as EMM386 lends out 16KB logical
+//! pages, which are made visible by mapping them into the four physical
+//! pages of the page frame, a 64KB window in upper memory. This covers the
+//! LIM 4.0 functions of interrupt 67h that are needed for that.
+//!
+//! Implementation progress:
+//!
+//! | Op   | Function call                                           | Status |
+//! |------|---------------------------------------------------------|--------|
+//! | 40   | [Get status](Ems::detect)                               |   ✓    |
+//! | 41   | [Get page frame segment](Ems::page_frame_segment)       |   ✓    |
+//! | 42   | [Get unallocated page count](Ems::free_pages)           |   ✓    |
+//! | 43   | [Allocate pages](Ems::allocate)                         |   ✓    |
+//! | 44   | [Map/unmap handle page](EmsHandle::map)                 |   ✓    |
+//! | 45   | [Deallocate pages](EmsHandle)                           |   ✓    |
+//! | 46   | [Get version](Ems::version)                             |   ✓    |
+//! | 4B   | Get handle count                                        |        |
+//! | 4C   | [Get handle pages](EmsHandle::pages)                    |   ✓    |
+//! | 51   | [Reallocate pages](EmsHandle::resize)                   |   ✓    |
+//!
+//! ```
+//! let ems = Ems::detect().expect("no expanded memory manager");
+//! let handle = ems.allocate(8)?; // 128KB
+//!
+//! handle.map(0, 5)?;
+//! let window = ems.window(0).unwrap();
+//! unsafe { window.write_bytes(0, PAGE_SIZE) };
+//! ```
+//!
+//! References:
+//! * [LIM EMS 4.0 specification](http://www.phatcode.net/res/218/files/limems40.txt)
+//! * [INT 67h (Ralf Brown's Interrupt List)](https://www.ctyme.com/intr/int-67.htm)
+
+use core::arch::asm;
+use core::fmt;
+
+use crate::dos::far_ptr::FarPtr;
+use crate::dos::memory;
+
+/// Size of a logical or physical page in bytes
+pub const PAGE_SIZE: usize = 0x4000;
+/// Number of physical pages in the page frame
+pub const PHYSICAL_PAGES: u8 = 4;
+
+/// Name of the EMM device driver, found in the header of the INT 67h handler
+const DEVICE_NAME: &[u8; 8] = b"EMMXXXX0";
+/// Offset of the device name in the driver header
+const DEVICE_NAME_OFFSET: u16 = 0x0A;
+/// Logical page number that unmaps a physical page
+const UNMAP_PAGE: u16 = 0xFFFF;
+
+/// Errors reported by the EMM in AH
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmsError {
+    InternalError = 0x80,
+    HardwareMalfunction = 0x81,
+    Busy = 0x82,
+    InvalidHandle = 0x83,
+    UndefinedFunction = 0x84,
+    OutOfHandles = 0x85,
+    MappingContextError = 0x86,
+    MoreThanTotalPages = 0x87,
+    MoreThanFreePages = 0x88,
+    ZeroPages = 0x89,
+    LogicalPageOutOfRange = 0x8A,
+    PhysicalPageOutOfRange = 0x8B,
+    SaveAreaFull = 0x8C,
+    ContextAlreadySaved = 0x8D,
+    NoSavedContext = 0x8E,
+    InvalidSubfunction = 0x8F,
+    UnknownError = 0xFF, // Not an EMS error code
+}
+
+impl EmsError {
+    pub fn from_u8(value: u8) -> Option<EmsError> {
+        match value {
+            0x80 => Some(EmsError::InternalError),
+            0x81 => Some(EmsError::HardwareMalfunction),
+            0x82 => Some(EmsError::Busy),
+            0x83 => Some(EmsError::InvalidHandle),
+            0x84 => Some(EmsError::UndefinedFunction),
+            0x85 => Some(EmsError::OutOfHandles),
+            0x86 => Some(EmsError::MappingContextError),
+            0x87 => Some(EmsError::MoreThanTotalPages),
+            0x88 => Some(EmsError::MoreThanFreePages),
+            0x89 => Some(EmsError::ZeroPages),
+            0x8A => Some(EmsError::LogicalPageOutOfRange),
+            0x8B => Some(EmsError::PhysicalPageOutOfRange),
+            0x8C => Some(EmsError::SaveAreaFull),
+            0x8D => Some(EmsError::ContextAlreadySaved),
+            0x8E => Some(EmsError::NoSavedContext),
+            0x8F => Some(EmsError::InvalidSubfunction),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmsError::InternalError => "Internal error in the memory manager",
+            EmsError::HardwareMalfunction => "Expanded memory hardware malfunction",
+            EmsError::Busy => "Memory manager busy",
+            EmsError::InvalidHandle => "Invalid handle",
+            EmsError::UndefinedFunction => "Undefined function",
+            EmsError::OutOfHandles => "No more handles available",
+            EmsError::MappingContextError => "Error saving or restoring mapping context",
+            EmsError::MoreThanTotalPages => "More pages requested than exist",
+            EmsError::MoreThanFreePages => "More pages requested than are free",
+            EmsError::ZeroPages => "Zero pages requested",
+            EmsError::LogicalPageOutOfRange => "Logical page out of range",
+            EmsError::PhysicalPageOutOfRange => "Physical page out of range",
+            EmsError::SaveAreaFull => "Page map save area full",
+            EmsError::ContextAlreadySaved => "Mapping context already saved",
+            EmsError::NoSavedContext => "No saved mapping context",
+            EmsError::InvalidSubfunction => "Invalid subfunction",
+            EmsError::UnknownError => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Display for EmsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Expanded memory pages that can still be allocated. See [Ems::free_pages]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageCount {
+    pub free: u16,
+    pub total: u16,
+}
+
+/// Handle to an installed expanded memory manager
+#[derive(Debug, Clone, Copy)]
+pub struct Ems {
+    page_frame: u16,
+}
+
+/// Call INT 67h, returning AX, BX and DX. Fails if AH isn't 0 afterwards
+fn call(ax: u16, bx: u16, dx: u16) -> Result<(u16, u16, u16), EmsError> {
+    let ax_out: u16;
+    let bx_out: u16;
+    let dx_out: u16;
+
+    unsafe {
+        asm!("int 0x67",
+            inout("ax") ax => ax_out,
+            inout("bx") bx => bx_out,
+            inout("dx") dx => dx_out,
+        );
+    }
+
+    let status = (ax_out >> 8) as u8;
+    if status != 0 {
+        return Err(EmsError::from_u8(status).unwrap_or(EmsError::UnknownError));
+    }
+
+    Ok((ax_out, bx_out, dx_out))
+}
+
+impl Ems {
+    /// Look for an expanded memory manager. The INT 67h handler has to
+    /// belong to a driver called `EMMXXXX0` that reports itself working (40h)
+    pub fn detect() -> Option<Ems> {
+        let segment: u16;
+
+        // Get the interrupt vector (21h 35h)
+        unsafe {
+            asm!(
+                "mov cx, es",       // Preserve extra segment register
+                "int 0x21",
+                "mov dx, es",
+                "mov es, cx",       // Restore extra segment register
+                inout("ax") 0x3567u16 => _,
+                out("bx") _,
+                out("cx") _,
+                out("dx") segment,
+            );
+        }
+
+        let name_matches = DEVICE_NAME.iter()
+            .enumerate()
+            .all(|(index, &character)| FarPtr::new(segment, DEVICE_NAME_OFFSET + index as u16).read_byte() == character);
+
+        if !name_matches {
+            return None;
+        }
+
+        call(0x4000, 0, 0).ok()?;
+        let (_, page_frame, _) = call(0x4100, 0, 0).ok()?;
+
+        Some(Ems {
+            page_frame,
+        })
+    }
+
+    /// Version of the specification the manager implements (46h), as major
+    /// and minor
+    pub fn version(&self) -> Result<(u8, u8), EmsError> {
+        let (version, _, _) = call(0x4600, 0, 0)?;
+
+        Ok(((version as u8) >> 4, version as u8 & 0xF))
+    }
+
+    /// Segment of the page frame (41h)
+    pub fn page_frame_segment(&self) -> u16 {
+        self.page_frame
+    }
+
+    /// Pointer to one of the physical pages in the page frame. Whatever
+    /// logical page was last mapped there is visible through it
+    pub fn window(&self, physical_page: u8) -> Option<*mut u8> {
+        if physical_page >= PHYSICAL_PAGES {
+            return None;
+        }
+
+        let paragraphs = (PAGE_SIZE / memory::PARAGRAPH_SIZE) as u16;
+        memory::segment_to_ptr(self.page_frame + physical_page as u16 * paragraphs)
+    }
+
+    /// Free and total number of pages (42h)
+    pub fn free_pages(&self) -> Result<PageCount, EmsError> {
+        let (_, free, total) = call(0x4200, 0, 0)?;
+
+        Ok(PageCount {
+            free,
+            total,
+        })
+    }
+
+    /// Allocate `pages` logical pages (43h). They are released when the
+    /// returned handle is dropped
+    pub fn allocate(&self, pages: u16) -> Result<EmsHandle, EmsError> {
+        let (_, _, handle) = call(0x4300, pages, 0)?;
+
+        Ok(EmsHandle {
+            handle,
+        })
+    }
+}
+
+/// Logical pages allocated from the expanded memory manager, deallocated
+/// when dropped (45h)
+#[derive(Debug)]
+pub struct EmsHandle {
+    handle: u16,
+}
+
+impl EmsHandle {
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+
+    /// Number of pages allocated to the handle (4Ch)
+    pub fn pages(&self) -> Result<u16, EmsError> {
+        let (_, pages, _) = call(0x4C00, 0, self.handle)?;
+
+        Ok(pages)
+    }
+
+    /// Map `logical_page` of this handle into `physical_page` of the page
+    /// frame (44h). See [Ems::window]
+    pub fn map(&self, physical_page: u8, logical_page: u16) -> Result<(), EmsError> {
+        call(0x4400 | physical_page as u16, logical_page, self.handle)?;
+
+        Ok(())
+    }
+
+    /// Unmap whatever is in `physical_page` (44h). Needs LIM 4.0
+    pub fn unmap(&self, physical_page: u8) -> Result<(), EmsError> {
+        self.map(physical_page, UNMAP_PAGE)
+    }
+
+    /// Grow or shrink the handle to `pages` pages (51h). Needs LIM 4.0
+    pub fn resize(&mut self, pages: u16) -> Result<(), EmsError> {
+        call(0x5100, pages, self.handle)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for EmsHandle {
+    fn drop(&mut self) {
+        let _ = call(0x4500, 0, self.handle);
+    }
+}
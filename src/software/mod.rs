@@ -1 +1,3 @@
-pub mod mouse;
\ No newline at end of file
+pub mod ems;
+pub mod mouse;
+pub mod xms;
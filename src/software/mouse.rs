@@ -15,7 +15,7 @@
 
 use core::arch::asm;
 
-use crate::dos::misc::ptr_to_segments;
+use crate::dos::far_ptr::{self, BufferLocation, BufferRegisters, FarPtr};
 
 pub struct Mouse {}
 
@@ -163,27 +163,15 @@ impl Mouse {
     ///     Mouse::cursor_show();
     /// ```
     pub fn set_graphics_cursor(x_point: u16, y_point: u16, bitmap: &[[u16; 16]; 2]) {
-        
-        let (segment, offset) = ptr_to_segments(bitmap.as_ptr() as u32);
+        let registers = BufferRegisters {
+            ax: 0x0009,
+            bx: x_point,
+            cx: y_point,
+            ..Default::default()
+        };
 
         unsafe {
-            asm!(
-                "mov ax, es",   // Stash ES register and change it to point to
-                "push ax",      // an offset from CS
-                "mov ax, cs",
-                "add ax, di",
-                "mov es, ax",
-
-                "mov ax, 0x0009",
-                "int 0x33",
-
-                "pop ax",       // Restore ES from the stack
-                "mov es, ax",
-                in("bx") x_point,
-                in("cx") y_point,
-                in("di") segment,
-                in("dx") offset,
-            )
+            far_ptr::interrupt_with_buffer(0x33, BufferLocation::EsDx, FarPtr::from_ptr(bitmap.as_ptr()), registers);
         }
     }
-}
+}
\ No newline at end of file
@@ -0,0 +1,545 @@
+//! eXtended Memory Specification (XMS) driver interface
+//! ======================================================================
+//!
+//! Memory above 1MB can't be addressed from real mode, but an XMS driver such
+//! as HIMEM.SYS hands it out in extended memory blocks (EMBs) and copies data
+//! between them and conventional memory. The driver is found through the
+//! multiplex interrupt 2Fh and then called through a far entry point.
+//!
+//! Implementation progress:
+//!
+//! | Op   | Function call                                           | Status |
+//! |------|---------------------------------------------------------|--------|
+//! | 00   | [Get XMS version](Xms::version)                         |   ✓    |
+//! | 01   | Request high memory area                                |        |
+//! | 02   | Release high memory area                                |        |
+//! | 03   | Global enable A20                                       |        |
+//! | 04   | Global disable A20                                      |        |
+//! | 05   | Local enable A20                                        |        |
+//! | 06   | Local disable A20                                       |        |
+//! | 07   | Query A20                                               |        |
+//! | 08   | [Query free extended memory](Xms::free_memory)          |   ✓    |
+//! | 09   | [Allocate extended memory block](Xms::allocate)         |   ✓    |
+//! | 0A   | [Free extended memory block](ExtendedMemoryBlock)       |   ✓    |
+//! | 0B   | [Move extended memory block](ExtendedMemoryBlock::read) |   ✓    |
+//! | 0C   | [Lock extended memory block](ExtendedMemoryBlock::lock) |   ✓    |
+//! | 0D   | [Unlock extended memory block](LockedBlock)             |   ✓    |
+//! | 0E   | [Get EMB handle information](ExtendedMemoryBlock::info) |   ✓    |
+//! | 0F   | [Resize EMB](ExtendedMemoryBlock::resize)               |   ✓    |
+//! | 10   | Request upper memory block                              |        |
+//! | 11   | Release upper memory block                              |        |
+//!
+//! ```
+//! let xms = Xms::detect().expect("HIMEM.SYS isn't loaded");
+//! let mut buffer = XmsBuffer::new(xms, 200_000)?;
+//! buffer.write(150_000, b"far away")?;
+//!
+//! let mut data = [0; 8];
+//! buffer.read(150_000, &mut data)?;
+//! ```
+//!
+//! References:
+//! * [eXtended Memory Specification 3.0](http://www.phatcode.net/res/219/files/xms30.txt)
+//! * [INT 2Fh 4310h (Ralf Brown's Interrupt List)](https://www.ctyme.com/intr/rb-4768.htm)
+
+use core::arch::asm;
+use core::fmt;
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::dos::far_ptr::FarPtr;
+
+/// Errors reported by the XMS driver in BL
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XmsError {
+    NotImplemented = 0x80,
+    VdiskDetected = 0x81,
+    A20Error = 0x82,
+    GeneralDriverError = 0x8E,
+    UnrecoverableDriverError = 0x8F,
+    OutOfMemory = 0xA0,
+    OutOfHandles = 0xA1,
+    InvalidHandle = 0xA2,
+    InvalidSourceHandle = 0xA3,
+    InvalidSourceOffset = 0xA4,
+    InvalidDestinationHandle = 0xA5,
+    InvalidDestinationOffset = 0xA6,
+    InvalidLength = 0xA7,
+    InvalidOverlap = 0xA8,
+    ParityError = 0xA9,
+    BlockNotLocked = 0xAA,
+    BlockLocked = 0xAB,
+    LockCountOverflow = 0xAC,
+    LockFailed = 0xAD,
+    UnknownError = 0xFF, // Not an XMS error code
+}
+
+impl XmsError {
+    pub fn from_u8(value: u8) -> Option<XmsError> {
+        match value {
+            0x80 => Some(XmsError::NotImplemented),
+            0x81 => Some(XmsError::VdiskDetected),
+            0x82 => Some(XmsError::A20Error),
+            0x8E => Some(XmsError::GeneralDriverError),
+            0x8F => Some(XmsError::UnrecoverableDriverError),
+            0xA0 => Some(XmsError::OutOfMemory),
+            0xA1 => Some(XmsError::OutOfHandles),
+            0xA2 => Some(XmsError::InvalidHandle),
+            0xA3 => Some(XmsError::InvalidSourceHandle),
+            0xA4 => Some(XmsError::InvalidSourceOffset),
+            0xA5 => Some(XmsError::InvalidDestinationHandle),
+            0xA6 => Some(XmsError::InvalidDestinationOffset),
+            0xA7 => Some(XmsError::InvalidLength),
+            0xA8 => Some(XmsError::InvalidOverlap),
+            0xA9 => Some(XmsError::ParityError),
+            0xAA => Some(XmsError::BlockNotLocked),
+            0xAB => Some(XmsError::BlockLocked),
+            0xAC => Some(XmsError::LockCountOverflow),
+            0xAD => Some(XmsError::LockFailed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            XmsError::NotImplemented => "Function not implemented",
+            XmsError::VdiskDetected => "VDISK device detected",
+            XmsError::A20Error => "A20 error",
+            XmsError::GeneralDriverError => "General driver error",
+            XmsError::UnrecoverableDriverError => "Unrecoverable driver error",
+            XmsError::OutOfMemory => "All extended memory is allocated",
+            XmsError::OutOfHandles => "All extended memory handles are in use",
+            XmsError::InvalidHandle => "Invalid handle",
+            XmsError::InvalidSourceHandle => "Invalid source handle",
+            XmsError::InvalidSourceOffset => "Invalid source offset",
+            XmsError::InvalidDestinationHandle => "Invalid destination handle",
+            XmsError::InvalidDestinationOffset => "Invalid destination offset",
+            XmsError::InvalidLength => "Invalid length",
+            XmsError::InvalidOverlap => "Invalid overlap in move",
+            XmsError::ParityError => "Parity error",
+            XmsError::BlockNotLocked => "Block is not locked",
+            XmsError::BlockLocked => "Block is locked",
+            XmsError::LockCountOverflow => "Lock count overflow",
+            XmsError::LockFailed => "Lock failed",
+            XmsError::UnknownError => "Unknown error",
+        }
+    }
+}
+
+impl fmt::Display for XmsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Version of the XMS driver. See [Xms::version]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XmsVersion {
+    /// Version of the XMS specification, in BCD
+    pub version: u16,
+    /// Driver's own revision number, in BCD
+    pub revision: u16,
+    /// Whether the high memory area exists
+    pub hma_exists: bool,
+}
+
+impl XmsVersion {
+    pub fn major(&self) -> u8 {
+        (self.version >> 8) as u8
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.version as u8
+    }
+}
+
+/// Extended memory that can still be allocated, in KB. See
+/// [Xms::free_memory]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FreeMemory {
+    pub largest_block: u16,
+    pub total: u16,
+}
+
+/// State of an extended memory block. See [ExtendedMemoryBlock::info]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockInfo {
+    pub lock_count: u8,
+    /// Handles the driver still has available
+    pub free_handles: u8,
+    /// Size of the block in KB
+    pub size: u16,
+}
+
+/// Handle to an installed XMS driver
+#[derive(Debug, Clone, Copy)]
+pub struct Xms {
+    /// Far pointer to the driver, offset in the low word
+    entry: u32,
+}
+
+impl Xms {
+    /// Look for an XMS driver (2F 4300h) and get its entry point (2F 4310h)
+    pub fn detect() -> Option<Xms> {
+        let installed: u16;
+
+        unsafe {
+            asm!("int 0x2f",
+                inout("ax") 0x4300u16 => installed,
+            );
+        }
+
+        if installed as u8 != 0x80 {
+            return None;
+        }
+
+        let segment: u16;
+        let offset: u16;
+
+        unsafe {
+            asm!(
+                "mov cx, es",       // Preserve extra segment register
+                "int 0x2f",
+                "mov dx, es",
+                "mov es, cx",       // Restore extra segment register
+                inout("ax") 0x4310u16 => _,
+                out("bx") offset,
+                out("cx") _,
+                out("dx") segment,
+            );
+        }
+
+        Some(Xms {
+            entry: (segment as u32) << 16 | offset as u32,
+        })
+    }
+
+    /// Call the driver with AH set to `function`. Returns AX, BX and DX
+    fn call(&self, function: u8, bx: u16, dx: u16) -> (u16, u16, u16) {
+        // Keep the pointer in our stack segment, where it's always reachable
+        let entry = self.entry;
+        let ax: u16;
+        let bx_out: u16;
+        let dx_out: u16;
+
+        unsafe {
+            asm!(
+                "lcall [{entry}]",
+                entry = in(reg) &entry,
+                inout("ax") (function as u16) << 8 => ax,
+                inout("bx") bx => bx_out,
+                inout("dx") dx => dx_out,
+                out("cx") _,
+            );
+        }
+
+        (ax, bx_out, dx_out)
+    }
+
+    /// Like [Xms::call] for functions that report success with AX = 1 and
+    /// failure with an error code in BL
+    fn call_checked(&self, function: u8, bx: u16, dx: u16) -> Result<(u16, u16), XmsError> {
+        let (ax, bx, dx) = self.call(function, bx, dx);
+
+        if ax != 1 {
+            return Err(XmsError::from_u8(bx as u8).unwrap_or(XmsError::UnknownError));
+        }
+
+        Ok((bx, dx))
+    }
+
+    /// Get the driver version (00h)
+    pub fn version(&self) -> XmsVersion {
+        let (version, revision, hma_exists) = self.call(0x00, 0, 0);
+
+        XmsVersion {
+            version,
+            revision,
+            hma_exists: hma_exists & 1 != 0,
+        }
+    }
+
+    /// Query free extended memory (08h)
+    pub fn free_memory(&self) -> Result<FreeMemory, XmsError> {
+        let (largest_block, error, total) = self.call(0x08, 0, 0);
+
+        // Running out of memory is reported as an error, but isn't one here
+        if largest_block == 0 && error as u8 != 0 && error as u8 != XmsError::OutOfMemory as u8 {
+            return Err(XmsError::from_u8(error as u8).unwrap_or(XmsError::UnknownError));
+        }
+
+        Ok(FreeMemory {
+            largest_block,
+            total,
+        })
+    }
+
+    /// Allocate an extended memory block of `kilobytes` KB (09h). It's freed
+    /// when the returned block is dropped
+    pub fn allocate(&self, kilobytes: u16) -> Result<ExtendedMemoryBlock, XmsError> {
+        let (_, handle) = self.call_checked(0x09, 0, kilobytes)?;
+
+        Ok(ExtendedMemoryBlock {
+            xms: *self,
+            handle,
+        })
+    }
+
+    /// Copy `length` bytes between memory blocks (0Bh). Handle 0 stands for
+    /// conventional memory, with the offset being a segment:offset pair
+    fn move_memory(&self, parameters: &MoveParameters) -> Result<(), XmsError> {
+        let entry = self.entry;
+        let result: u16;
+        let error: u16;
+
+        unsafe {
+            asm!(
+                "push esi",         // SI can't be handed to asm directly
+                "mov esi, {parameters}",
+                "lcall [{entry}]",
+                "pop esi",
+                entry = in(reg) &entry,
+                parameters = in(reg) parameters,
+                inout("ax") 0x0B00u16 => result,
+                out("bx") error,
+                out("cx") _,
+                out("dx") _,
+            );
+        }
+
+        if result != 1 {
+            return Err(XmsError::from_u8(error as u8).unwrap_or(XmsError::UnknownError));
+        }
+
+        Ok(())
+    }
+}
+
+/// Extended memory move structure for function 0Bh
+#[repr(C, packed)]
+struct MoveParameters {
+    length: u32,
+    source_handle: u16,
+    source_offset: u32,
+    destination_handle: u16,
+    destination_offset: u32,
+}
+
+/// Far pointer the XMS driver understands for conventional memory
+fn conventional_address(ptr: *const u8) -> u32 {
+    FarPtr::from_ptr(ptr).into()
+}
+
+/// An extended memory block, freed when dropped (0Ah)
+#[derive(Debug)]
+pub struct ExtendedMemoryBlock {
+    xms: Xms,
+    handle: u16,
+}
+
+impl ExtendedMemoryBlock {
+    pub fn handle(&self) -> u16 {
+        self.handle
+    }
+
+    /// Lock count, free handles and size of the block (0Eh)
+    pub fn info(&self) -> Result<BlockInfo, XmsError> {
+        let (counts, size) = self.xms.call_checked(0x0E, 0, self.handle)?;
+
+        Ok(BlockInfo {
+            lock_count: (counts >> 8) as u8,
+            free_handles: counts as u8,
+            size,
+        })
+    }
+
+    /// Size of the block in bytes
+    pub fn size(&self) -> Result<u32, XmsError> {
+        Ok(self.info()?.size as u32 * 1024)
+    }
+
+    /// Grow or shrink the block to `kilobytes` KB (0Fh). The block can't be
+    /// locked
+    pub fn resize(&mut self, kilobytes: u16) -> Result<(), XmsError> {
+        self.xms.call_checked(0x0F, kilobytes, self.handle)?;
+
+        Ok(())
+    }
+
+    /// Lock the block in place (0Ch) to find its 32-bit linear address,
+    /// for use with protected mode or DMA. Unlocked again when the guard is
+    /// dropped
+    pub fn lock(&mut self) -> Result<LockedBlock<'_>, XmsError> {
+        let (low, high) = self.xms.call_checked(0x0C, 0, self.handle)?;
+
+        Ok(LockedBlock {
+            block: self,
+            address: (high as u32) << 16 | low as u32,
+        })
+    }
+
+    /// Copy `data` into the block at `offset` (0Bh)
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), XmsError> {
+        // The driver only moves whole words, so the odd byte at the end is
+        // merged into a word read back from the block
+        let even = data.len() & !1;
+
+        if even > 0 {
+            self.xms.move_memory(&MoveParameters {
+                length: even as u32,
+                source_handle: 0,
+                source_offset: conventional_address(data.as_ptr()),
+                destination_handle: self.handle,
+                destination_offset: offset,
+            })?;
+        }
+
+        if even < data.len() {
+            let tail_offset = offset + even as u32;
+            let mut word = [0u8; 2];
+            self.read(tail_offset, &mut word)?;
+            word[0] = data[even];
+
+            self.xms.move_memory(&MoveParameters {
+                length: 2,
+                source_handle: 0,
+                source_offset: conventional_address(word.as_ptr()),
+                destination_handle: self.handle,
+                destination_offset: tail_offset,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy from the block at `offset` into `data` (0Bh)
+    pub fn read(&self, offset: u32, data: &mut [u8]) -> Result<(), XmsError> {
+        let even = data.len() & !1;
+
+        if even > 0 {
+            self.xms.move_memory(&MoveParameters {
+                length: even as u32,
+                source_handle: self.handle,
+                source_offset: offset,
+                destination_handle: 0,
+                destination_offset: conventional_address(data.as_mut_ptr()),
+            })?;
+        }
+
+        if even < data.len() {
+            let mut word = [0u8; 2];
+
+            self.xms.move_memory(&MoveParameters {
+                length: 2,
+                source_handle: self.handle,
+                source_offset: offset + even as u32,
+                destination_handle: 0,
+                destination_offset: conventional_address(word.as_mut_ptr()),
+            })?;
+
+            data[even] = word[0];
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ExtendedMemoryBlock {
+    fn drop(&mut self) {
+        let _ = self.xms.call_checked(0x0A, 0, self.handle);
+    }
+}
+
+/// A locked extended memory block. See [ExtendedMemoryBlock::lock]
+pub struct LockedBlock<'a> {
+    block: &'a mut ExtendedMemoryBlock,
+    address: u32,
+}
+
+impl LockedBlock<'_> {
+    /// Linear address of the block
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+}
+
+impl Drop for LockedBlock<'_> {
+    fn drop(&mut self) {
+        let _ = self.block.xms.call_checked(0x0D, 0, self.block.handle);
+    }
+}
+
+/// A byte buffer kept in extended memory, for data sets that don't fit in
+/// conventional memory. Reads and writes are checked against its length
+#[derive(Debug)]
+pub struct XmsBuffer {
+    block: ExtendedMemoryBlock,
+    len: u32,
+}
+
+impl XmsBuffer {
+    /// Allocate a buffer of `len` bytes. Its contents are undefined
+    pub fn new(xms: Xms, len: u32) -> Result<Self, XmsError> {
+        // Whole kilobytes, with room for the word moved for an odd byte at
+        // the end, which reaches one byte past it
+        let kilobytes = (len + 2 + 1023) / 1024;
+        let kilobytes = u16::try_from(kilobytes.max(1)).map_err(|_| XmsError::OutOfMemory)?;
+
+        Ok(Self {
+            block: xms.allocate(kilobytes)?,
+            len,
+        })
+    }
+
+    /// Allocate a buffer holding a copy of `data`
+    pub fn from_slice(xms: Xms, data: &[u8]) -> Result<Self, XmsError> {
+        let mut buffer = Self::new(xms, data.len() as u32)?;
+        buffer.write(0, data)?;
+
+        Ok(buffer)
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy `data` into the buffer at `offset`
+    pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), XmsError> {
+        if !self.in_bounds(offset, data.len()) {
+            return Err(XmsError::InvalidDestinationOffset);
+        }
+
+        self.block.write(offset, data)
+    }
+
+    /// Fill `data` from the buffer at `offset`
+    pub fn read(&self, offset: u32, data: &mut [u8]) -> Result<(), XmsError> {
+        if !self.in_bounds(offset, data.len()) {
+            return Err(XmsError::InvalidSourceOffset);
+        }
+
+        self.block.read(offset, data)
+    }
+
+    /// Copy `len` bytes at `offset` into conventional memory
+    pub fn to_vec(&self, offset: u32, len: usize) -> Result<Vec<u8>, XmsError> {
+        let mut data = vec![0; len];
+        self.read(offset, &mut data)?;
+
+        Ok(data)
+    }
+
+    /// The extended memory block behind the buffer
+    pub fn block(&mut self) -> &mut ExtendedMemoryBlock {
+        &mut self.block
+    }
+
+    fn in_bounds(&self, offset: u32, len: usize) -> bool {
+        offset.checked_add(len as u32).map_or(false, |end| end <= self.len)
+    }
+}
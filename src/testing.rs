@@ -0,0 +1,235 @@
+//! Test harness that runs inside DOS
+//! ======================================================================
+//!
+//! Tests are plain functions registered with [test_case] next to where
+//! they're defined. [run] starts a child copy of the program for each of
+//! them (`PROGRAM.COM /run NAME`) with its output going to a temporary
+//! file, so a failed assert only ends that child. Results are written in
+//! the [TAP](https://testanything.org) format to the screen, and with
+//! `/tap FILE` to a file or a device like `COM1` as well, where something
+//! outside the emulator can pick them up.
+//!
+//! ```
+//! test_case!(file_test);
+//! fn file_test() {
+//!     assert!(File::open("TEST.TXT\0", AccessMode::default()).is_ok());
+//! }
+//!
+//! entry!(main);
+//!
+//! fn main() -> u8 {
+//!     testing::run()
+//! }
+//! ```
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::dos::env;
+use crate::dos::error_code::ErrorCode;
+use crate::dos::file::{AccessMode, File, FileAttributes};
+use crate::dos::process::Command;
+use crate::EXIT_FAILURE;
+
+/// Where a child's output is kept while it runs
+const OUTPUT_FILE: &str = "TESTOUT.TMP";
+/// Most of a failed test's output that is copied into the report
+const OUTPUT_MAX: usize = 2048;
+
+/// A test function and the name it's reported and selected by
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+/// Register a function as a test, named after it
+///
+/// The [Test] goes in the `.tests` section, which the linker scripts
+/// gather into one array for [tests]. Nothing has to list the tests
+#[macro_export]
+macro_rules! test_case {
+    ($function:ident) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".tests"]
+            static TEST: $crate::testing::Test = $crate::testing::Test {
+                name: stringify!($function),
+                run: $function,
+            };
+        };
+    };
+}
+
+/// Every test registered with [test_case], in the order they were linked
+pub fn tests() -> &'static [Test] {
+    extern "C" {
+        static _tests_start: u8;
+        static _tests_end: u8;
+    }
+
+    let start = core::ptr::addr_of!(_tests_start) as *const Test;
+    let end = core::ptr::addr_of!(_tests_end) as *const Test;
+
+    unsafe {
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Run the registered tests the way the command line asks and return the
+/// exit code:
+///
+/// * `/run NAME` runs just that test in this process. Children are started
+///   this way
+/// * `/tap FILE` runs every test and writes the report to FILE as well
+/// * anything else runs every test with the report on the screen
+///
+/// The exit code is [EXIT_FAILURE] if any test failed
+pub fn run() -> u8 {
+    let tests = tests();
+    let args: Vec<String> = env::args().collect();
+
+    match args.as_slice() {
+        [flag, name] if flag.eq_ignore_ascii_case("/run") => run_single(tests, name),
+        [flag, path] if flag.eq_ignore_ascii_case("/tap") => run_all(tests, Some(path)),
+        _ => run_all(tests, None),
+    }
+}
+
+fn run_single(tests: &[Test], name: &str) -> u8 {
+    match tests.iter().find(|test| test.name.eq_ignore_ascii_case(name)) {
+        Some(test) => {
+            (test.run)();
+            0
+        },
+        None => {
+            println!("No test called {}", name);
+            EXIT_FAILURE
+        },
+    }
+}
+
+fn run_all(tests: &[Test], path: Option<&String>) -> u8 {
+    let mut report = match Report::new(path) {
+        Ok(report) => report,
+        Err(error) => {
+            println!("Can't write the report: {:?}", error);
+            return EXIT_FAILURE;
+        },
+    };
+
+    // DOS 2 doesn't say where the program was started from, so there is
+    // nothing to start children from. Tests then run in this process and the
+    // first failure ends the run
+    let program = env::program_path();
+    let mut failed = 0;
+
+    report.line(format_args!("TAP version 13"));
+    report.line(format_args!("1..{}", tests.len()));
+
+    for (index, test) in tests.iter().enumerate() {
+        let result = match &program {
+            Some(program) => run_child(program, test),
+            None => {
+                (test.run)();
+                Ok(())
+            },
+        };
+
+        match result {
+            Ok(()) => report.line(format_args!("ok {} - {}", index + 1, test.name)),
+            Err(failure) => {
+                failed += 1;
+                report.line(format_args!("not ok {} - {}", index + 1, test.name));
+
+                for line in failure.lines() {
+                    report.line(format_args!("# {}", line));
+                }
+            },
+        }
+    }
+
+    let _ = File::delete(OUTPUT_FILE);
+    report.line(format_args!("# {} passed, {} failed", tests.len() - failed, failed));
+
+    if failed > 0 {
+        EXIT_FAILURE
+    } else {
+        0
+    }
+}
+
+/// Run one test in a child process. A failure comes back with the exit code
+/// and whatever the test printed
+fn run_child(program: &str, test: &Test) -> Result<(), String> {
+    let output = File::create(OUTPUT_FILE, FileAttributes::NORMAL)
+        .map_err(|error| format!("Can't create the output file: {:?}", error))?;
+
+    let status = Command::new(program)
+        .arg("/run")
+        .arg(test.name)
+        .stdout(&output)
+        .stderr(&output)
+        .status();
+    drop(output);
+
+    let status = status.map_err(|error| format!("Can't start {}: {:?}", program, error))?;
+
+    if status.success() {
+        return Ok(());
+    }
+
+    let mut failure = format!("Exit code {} ({:?})\n", status.code(), status.termination());
+    failure.push_str(&read_output());
+
+    Err(failure)
+}
+
+/// Start of what the last child printed
+fn read_output() -> String {
+    let mut buffer = [0; OUTPUT_MAX];
+
+    let read = File::open(OUTPUT_FILE, AccessMode::default())
+        .and_then(|file| file.read(&mut buffer))
+        .unwrap_or(0);
+
+    String::from_utf8_lossy(&buffer[..read]).into_owned()
+}
+
+/// Report going to the screen and optionally a file or device
+struct Report {
+    file: Option<File>,
+}
+
+impl Report {
+    fn new(path: Option<&String>) -> Result<Self, ErrorCode> {
+        let file = match path {
+            Some(path) => Some(File::create(path, FileAttributes::NORMAL)?),
+            None => None,
+        };
+
+        Ok(Self {
+            file,
+        })
+    }
+
+    /// Write one line ending in CR LF. Errors writing the file are ignored,
+    /// the screen still shows the results
+    fn line(&mut self, args: fmt::Arguments) {
+        let _ = self.write_fmt(args);
+        let _ = self.write_str("\r\n");
+    }
+}
+
+impl Write for Report {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+
+        if let Some(file) = &self.file {
+            file.write(s.as_bytes()).map_err(|_| fmt::Error)?;
+        }
+
+        Ok(())
+    }
+}
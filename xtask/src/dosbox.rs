@@ -0,0 +1,120 @@
+//! Running programs in DOSBox, with a window or without one for tests
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::program::dos_name;
+
+/// Name the report is written to inside the emulator
+const REPORT_FILE: &str = "TESTS.TAP";
+/// Copied next to the program, the file tests read when there's no
+/// `C:\AUTOEXEC.BAT`
+const FIXTURES: &[&str] = &["README.md"];
+/// How often to check whether DOSBox is done
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A scratch directory mounted as C:, so whatever the tests create stays out
+/// of the repository. Removed when dropped
+pub struct Drive {
+    path: PathBuf,
+}
+
+impl Drive {
+    pub fn new() -> Result<Self, String> {
+        let path = env::temp_dir().join(format!("rust_dos_{}", std::process::id()));
+        fs::create_dir_all(&path)
+            .map_err(|error| format!("can't create {}: {}", path.display(), error))?;
+
+        Ok(Self {
+            path,
+        })
+    }
+
+    /// Copy `file` onto the drive under `name`
+    pub fn copy(&self, file: &Path, name: &str) -> Result<(), String> {
+        fs::copy(file, self.path.join(name))
+            .map(|_| ())
+            .map_err(|error| format!("can't copy {}: {}", file.display(), error))
+    }
+}
+
+impl Drop for Drive {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// DOSBox executable, from DOSBOX or the PATH
+fn dosbox() -> String {
+    env::var("DOSBOX").unwrap_or_else(|_| "dosbox".to_string())
+}
+
+/// Start `program` in DOSBox, which mounts its directory as C:, and wait
+/// until DOSBox is closed
+pub fn run(program: &Path) -> Result<(), String> {
+    let dosbox = dosbox();
+
+    Command::new(&dosbox)
+        .arg(program)
+        .status()
+        .map(|_| ())
+        .map_err(|error| format!("can't start {}: {}. Set DOSBOX to its path", dosbox, error))
+}
+
+/// Run `program` with `/tap` in DOSBox and return the report. The emulator
+/// is killed if it's still running after `timeout`
+pub fn run_tests(program: &Path, root: &Path, timeout: Duration) -> Result<String, String> {
+    let name = dos_name(program)?;
+    let drive = Drive::new()?;
+
+    drive.copy(program, &name)?;
+    for fixture in FIXTURES {
+        drive.copy(&root.join(fixture), fixture)?;
+    }
+
+    let dosbox = dosbox();
+    let mut child = Command::new(&dosbox)
+        .arg("-noautoexec")
+        .arg("-c").arg(format!("mount c \"{}\"", drive.path.display()))
+        .arg("-c").arg("c:")
+        .arg("-c").arg(format!("{} /tap {}", name, REPORT_FILE))
+        .arg("-c").arg("exit")
+        // No window or sound, so it runs on CI machines too
+        .env("SDL_VIDEODRIVER", "dummy")
+        .env("SDL_AUDIODRIVER", "dummy")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|error| format!("can't start {}: {}. Set DOSBOX to its path", dosbox, error))?;
+
+    let started = Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if started.elapsed() < timeout => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{} didn't finish within {} seconds", name, timeout.as_secs()));
+            },
+            Err(error) => return Err(format!("can't wait for {}: {}", dosbox, error)),
+        }
+    }
+
+    // DOSBox keeps long names for files it creates, but the case can differ
+    let report = fs::read_dir(&drive.path)
+        .map_err(|error| format!("can't read {}: {}", drive.path.display(), error))?
+        .filter_map(Result::ok)
+        .find(|entry| entry.file_name().eq_ignore_ascii_case(REPORT_FILE))
+        .ok_or_else(|| format!("{} didn't write {}", name, REPORT_FILE))?;
+
+    let report = fs::read(report.path())
+        .map_err(|error| format!("can't read {}: {}", REPORT_FILE, error))?;
+
+    Ok(String::from_utf8_lossy(&report).into_owned())
+}
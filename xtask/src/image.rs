@@ -0,0 +1,125 @@
+//! Copying the program into a FAT disk image, with mtools
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use crate::program::dos_name;
+
+const SECTOR_SIZE: usize = 512;
+/// Where the partition table starts in the master boot record
+const PARTITION_TABLE: usize = 0x1BE;
+const PARTITION_ENTRY_SIZE: usize = 16;
+/// FAT12, FAT16 and FAT32 partition types, with and without LBA
+const FAT_PARTITION_TYPES: &[u8] = &[0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E];
+
+/// Byte offset of the FAT file system in an image starting with
+/// `first_sector`: 0 for floppies and other images without a partition
+/// table, or else the start of the first FAT partition
+pub fn filesystem_offset(first_sector: &[u8]) -> Result<u64, String> {
+    if first_sector.len() < SECTOR_SIZE || first_sector[510..512] != [0x55, 0xAA] {
+        return Err("the image has no boot sector".to_string());
+    }
+
+    if is_fat_boot_sector(first_sector) {
+        return Ok(0);
+    }
+
+    first_sector[PARTITION_TABLE..PARTITION_TABLE + 4 * PARTITION_ENTRY_SIZE]
+        .chunks(PARTITION_ENTRY_SIZE)
+        .find(|entry| FAT_PARTITION_TYPES.contains(&entry[4]))
+        .map(|entry| u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as u64 * SECTOR_SIZE as u64)
+        .ok_or_else(|| "the image has no FAT partition".to_string())
+}
+
+/// Whether a sector looks like the start of a FAT file system: a jump over
+/// the BIOS parameter block, and sane values in it
+fn is_fat_boot_sector(sector: &[u8]) -> bool {
+    let jump = sector[0] == 0xEB || sector[0] == 0xE9;
+    let bytes_per_sector = u16::from_le_bytes([sector[0x0B], sector[0x0C]]);
+    let sectors_per_cluster = sector[0x0D];
+    let fats = sector[0x10];
+
+    jump
+        && bytes_per_sector.is_power_of_two() && (512..=4096).contains(&bytes_per_sector)
+        && sectors_per_cluster.is_power_of_two()
+        && (1..=2).contains(&fats)
+}
+
+/// Offset of the file system in the image at `path`. See [filesystem_offset]
+pub fn image_offset(path: &Path) -> Result<u64, String> {
+    let mut sector = [0; SECTOR_SIZE];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut sector))
+        .map_err(|error| format!("can't read {}: {}", path.display(), error))?;
+
+    filesystem_offset(&sector)
+}
+
+/// Copy `program` into the root directory of `image`, replacing any file of
+/// the same name
+pub fn copy(image: &Path, program: &Path) -> Result<(), String> {
+    let name = dos_name(program)?;
+    let offset = image_offset(image)?;
+    let mcopy = env::var("MCOPY").unwrap_or_else(|_| "mcopy".to_string());
+
+    let status = Command::new(&mcopy)
+        .arg("-o")
+        .arg("-i").arg(format!("{}@@{}", image.display(), offset))
+        .arg(program)
+        .arg(format!("::/{}", name))
+        .status()
+        .map_err(|error| format!("can't start {}: {}. Install mtools or set MCOPY", mcopy, error))?;
+
+    if !status.success() {
+        return Err(format!("{} couldn't copy {} into {}", mcopy, name, image.display()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boot_sector() -> Vec<u8> {
+        let mut sector = vec![0; SECTOR_SIZE];
+        sector[510] = 0x55;
+        sector[511] = 0xAA;
+
+        sector
+    }
+
+    #[test]
+    fn floppy_starts_with_file_system() {
+        let mut sector = boot_sector();
+        sector[0] = 0xEB;
+        sector[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        sector[0x0D] = 1;
+        sector[0x10] = 2;
+
+        assert_eq!(filesystem_offset(&sector), Ok(0));
+    }
+
+    #[test]
+    fn hard_disk_uses_first_fat_partition() {
+        let mut sector = boot_sector();
+        sector[0] = 0xFA;
+
+        let second = PARTITION_TABLE + PARTITION_ENTRY_SIZE;
+        sector[PARTITION_TABLE + 4] = 0x83; // Linux, skipped
+        sector[second + 4] = 0x06;
+        sector[second + 8..second + 12].copy_from_slice(&63u32.to_le_bytes());
+
+        assert_eq!(filesystem_offset(&sector), Ok(63 * 512));
+    }
+
+    #[test]
+    fn rejects_other_images() {
+        assert!(filesystem_offset(&[0; SECTOR_SIZE]).is_err());
+        assert!(filesystem_offset(&boot_sector()).is_err());
+    }
+}
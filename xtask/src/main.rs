@@ -0,0 +1,216 @@
+//! Host side helpers for rust_dos, run with `cargo dos <command>`
+
+mod dosbox;
+mod image;
+mod program;
+mod qemu;
+mod tap;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use program::{BuildOptions, Format};
+
+const USAGE: &str = "\
+Usage: cargo dos <command> [options]
+
+Commands:
+    build           Build the program as a .COM or .EXE file
+    run             Build the program and start it in DOSBox, or in QEMU
+    test [PROGRAM]  Run the program's tests in DOSBox and report the results.
+                    It's built first unless PROGRAM is given. Exits with 1 if
+                    a test failed
+
+Options:
+    --release           Build with the release profile
+    --exe               Build an MZ .EXE instead of a COM program
+    --features LIST     Cargo features to build with
+    -o, --output FILE   Where to put the program, next to the ELF file by
+                        default. Needs an 8.3 name to be run
+    --image IMAGE       Copy the program into this FAT disk image as well
+    --qemu              Boot IMAGE in QEMU instead of running the program in
+                        DOSBox
+    --timeout SECONDS   How long the tests may take, 120 by default
+
+Environment:
+    DOSBOX, QEMU, MCOPY and OBJCOPY override the tools that are used
+
+Anything that keeps a command from doing its job exits with 2.";
+
+/// How long a test run may take by default
+const DEFAULT_TIMEOUT: u64 = 120;
+
+/// Exit code for failing tests
+const EXIT_FAILED: u8 = 1;
+/// Exit code for anything that kept the command from doing its job
+const EXIT_ERROR: u8 = 2;
+
+/// Options shared by every command
+#[derive(Debug, Default)]
+struct Options {
+    build: BuildOptions,
+    image: Option<PathBuf>,
+    qemu: bool,
+    timeout: Option<u64>,
+    /// Program to test instead of building one
+    program: Option<PathBuf>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--release" => options.build.release = true,
+                "--exe" => options.build.format = Format::Exe,
+                "--features" => options.build.features = Some(value()?.clone()),
+                "-o" | "--output" => options.build.output = Some(PathBuf::from(value()?)),
+                "--image" => options.image = Some(PathBuf::from(value()?)),
+                "--qemu" => options.qemu = true,
+                "--timeout" => {
+                    let seconds = value()?;
+                    options.timeout = Some(seconds.parse().map_err(|_| format!("{} isn't a number of seconds", seconds))?);
+                },
+                _ if arg.starts_with('-') => return Err(format!("unknown option {}\n\n{}", arg, USAGE)),
+                _ if options.program.is_none() => options.program = Some(PathBuf::from(arg)),
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.split_first() {
+        Some((command, args)) => Options::parse(args).and_then(|options| match command.as_str() {
+            "build" => build(&options),
+            "run" => run(&options),
+            "test" => test(&options),
+            _ => Err(USAGE.to_string()),
+        }),
+        None => Err(USAGE.to_string()),
+    };
+
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        ExitCode::from(EXIT_ERROR)
+    })
+}
+
+/// Root of the rust_dos repository, where the program and test fixtures are
+fn repository() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..")
+}
+
+/// Build the program, and copy it into the image if there is one
+fn build_program(options: &Options) -> Result<PathBuf, String> {
+    if options.program.is_some() {
+        return Err(USAGE.to_string());
+    }
+
+    let program = program::build(&repository(), &options.build)?;
+    let size = fs::metadata(&program).map(|metadata| metadata.len()).unwrap_or(0);
+    eprintln!("Built {} ({} bytes)", program.display(), size);
+
+    if let Some(image) = &options.image {
+        image::copy(image, &program)?;
+        eprintln!("Copied it into {}", image.display());
+    }
+
+    Ok(program)
+}
+
+fn build(options: &Options) -> Result<ExitCode, String> {
+    build_program(options)?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run(options: &Options) -> Result<ExitCode, String> {
+    if options.qemu && options.image.is_none() {
+        return Err("--qemu needs a disk image to boot, given with --image".to_string());
+    }
+
+    let program = build_program(options)?;
+
+    match &options.image {
+        Some(image) if options.qemu => qemu::run(image)?,
+        _ => dosbox::run(&program)?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn test(options: &Options) -> Result<ExitCode, String> {
+    let program = match &options.program {
+        Some(program) => program.clone(),
+        None => build_program(options)?,
+    };
+
+    let timeout = Duration::from_secs(options.timeout.unwrap_or(DEFAULT_TIMEOUT));
+    let report = dosbox::run_tests(&program, &repository(), timeout)?;
+    print!("{}", report);
+
+    let summary = tap::parse(&report);
+    if summary.success() {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    match summary.planned {
+        Some(planned) if summary.passed + summary.failed.len() < planned =>
+            eprintln!("Only {} of {} tests reported", summary.passed + summary.failed.len(), planned),
+        None => eprintln!("The report has no test plan"),
+        _ => {},
+    }
+    for name in &summary.failed {
+        eprintln!("Failed: {}", name);
+    }
+
+    Ok(ExitCode::from(EXIT_FAILED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn parses_build_options() {
+        let options = parse(&["--release", "--exe", "--features", "dos-heap", "--image", "fd.img"]).unwrap();
+
+        assert!(options.build.release);
+        assert_eq!(options.build.format, Format::Exe);
+        assert_eq!(options.build.features.as_deref(), Some("dos-heap"));
+        assert_eq!(options.image, Some(PathBuf::from("fd.img")));
+        assert_eq!(options.program, None);
+    }
+
+    #[test]
+    fn takes_one_program() {
+        let options = parse(&["rust_dos.com", "--timeout", "30"]).unwrap();
+
+        assert_eq!(options.program, Some(PathBuf::from("rust_dos.com")));
+        assert_eq!(options.timeout, Some(30));
+        assert!(parse(&["a.com", "b.com"]).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["--features"]).is_err());
+        assert!(parse(&["--timeout", "soon"]).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Building the program and turning it into a file DOS can run
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The binary that's built, the one in src/main.rs
+pub const BIN_NAME: &str = "rust_dos";
+
+/// Largest COM program: a 64KB segment less the 256 byte PSP in front of it
+const COM_MAX_SIZE: usize = 0xFF00;
+/// Conventional memory, where the program, its PSP and everything it
+/// allocates on start have to fit
+const CONVENTIONAL_MEMORY: usize = 0xA0000;
+/// Size of the PSP DOS puts in front of the program
+const PSP_SIZE: usize = 0x100;
+/// Bytes in a paragraph, the unit EXE headers count memory in
+const PARAGRAPH_SIZE: usize = 16;
+/// Bytes in a page, the unit EXE headers count the file size in
+const PAGE_SIZE: usize = 512;
+
+/// What kind of program to build
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    /// Flat COM program linked with link.x
+    #[default]
+    Com,
+    /// MZ executable linked with exe.x
+    Exe,
+}
+
+impl Format {
+    /// Custom target, which picks the linker script. See .cargo/config.toml
+    fn target(self) -> &'static str {
+        match self {
+            Format::Com => "i586-rust_dos",
+            Format::Exe => "i586-rust_dos_exe",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Com => "com",
+            Format::Exe => "exe",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BuildOptions {
+    pub release: bool,
+    pub format: Format,
+    /// Passed on to `cargo build --features`
+    pub features: Option<String>,
+    /// Where to put the program. By default it goes next to the ELF file
+    pub output: Option<PathBuf>,
+}
+
+/// Build the program with cargo, strip it down to a flat binary and make sure
+/// DOS will be able to load it. Returns the path of the program
+pub fn build(root: &Path, options: &BuildOptions) -> Result<PathBuf, String> {
+    let target = options.format.target();
+    let profile = if options.release { "release" } else { "debug" };
+
+    let mut cargo = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()));
+    cargo.current_dir(root)
+        .arg("build")
+        .arg("-Zbuild-std=core,compiler_builtins,alloc")
+        .arg("-Zjson-target-spec")
+        .arg("--target").arg(format!("{}.json", target))
+        .arg("--bin").arg(BIN_NAME);
+
+    if options.release {
+        cargo.arg("--release");
+    }
+    if let Some(features) = &options.features {
+        cargo.arg("--features").arg(features);
+    }
+
+    run(&mut cargo, "cargo build")?;
+
+    let target_dir = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| root.join("target"));
+    let elf = target_dir.join(target).join(profile).join(BIN_NAME);
+    let output = options.output.clone()
+        .unwrap_or_else(|| elf.with_extension(options.format.extension()));
+
+    let mut objcopy = Command::new(objcopy(root)?);
+    objcopy.arg("-O").arg("binary").arg(&elf).arg(&output);
+    run(&mut objcopy, "objcopy")?;
+
+    let program = fs::read(&output)
+        .map_err(|error| format!("can't read {}: {}", output.display(), error))?;
+    check(options.format, &program)?;
+
+    Ok(output)
+}
+
+/// Run a build step, failing if it does
+fn run(command: &mut Command, name: &str) -> Result<(), String> {
+    let status = command.status()
+        .map_err(|error| format!("can't start {}: {}", name, error))?;
+
+    if !status.success() {
+        return Err(format!("{} failed", name));
+    }
+
+    Ok(())
+}
+
+/// objcopy from the llvm-tools component, where cargo-binutils finds it too,
+/// unless OBJCOPY says otherwise
+fn objcopy(root: &Path) -> Result<PathBuf, String> {
+    if let Some(objcopy) = env::var_os("OBJCOPY") {
+        return Ok(PathBuf::from(objcopy));
+    }
+
+    let sysroot = rustc(root, &["--print", "sysroot"])?;
+    let version = rustc(root, &["-vV"])?;
+    let host = version.lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .ok_or("can't tell the host from `rustc -vV`")?;
+
+    let path = Path::new(sysroot.trim()).join("lib/rustlib").join(host).join("bin/rust-objcopy");
+    if !path.exists() {
+        return Err("rust-objcopy is missing. Install it with `rustup component add llvm-tools-preview`, \
+            or set OBJCOPY".to_string());
+    }
+
+    Ok(path)
+}
+
+/// Output of rustc, run in the repository so it picks the same toolchain
+fn rustc(root: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+        .current_dir(root)
+        .args(args)
+        .output()
+        .map_err(|error| format!("can't start rustc: {}", error))?;
+
+    String::from_utf8(output.stdout).map_err(|_| "rustc printed something that isn't UTF-8".to_string())
+}
+
+/// Make sure DOS can load `program`
+pub fn check(format: Format, program: &[u8]) -> Result<(), String> {
+    match format {
+        Format::Com if program.is_empty() => Err("the program is empty".to_string()),
+        Format::Com if program.len() > COM_MAX_SIZE => Err(format!(
+            "{} bytes is too big for a COM program, which can have at most {}. Build an EXE with --exe",
+            program.len(), COM_MAX_SIZE)),
+        Format::Com => Ok(()),
+        Format::Exe => check_exe(program),
+    }
+}
+
+fn check_exe(program: &[u8]) -> Result<(), String> {
+    let word = |offset: usize| program.get(offset..offset + 2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize);
+
+    if program.get(0..2) != Some(b"MZ") {
+        return Err("the EXE header is missing".to_string());
+    }
+
+    let (Some(last_page), Some(pages), Some(header_paragraphs), Some(extra_paragraphs)) =
+        (word(0x02), word(0x04), word(0x08), word(0x0A)) else {
+        return Err("the EXE header is cut short".to_string());
+    };
+
+    let size = match last_page {
+        0 => pages * PAGE_SIZE,
+        _ => pages.saturating_sub(1) * PAGE_SIZE + last_page,
+    };
+    if size != program.len() {
+        return Err(format!("the EXE header gives a size of {} bytes, but the file has {}", size, program.len()));
+    }
+
+    let header_size = header_paragraphs * PARAGRAPH_SIZE;
+    if header_size > size {
+        return Err("the EXE header is bigger than the file".to_string());
+    }
+
+    let memory = PSP_SIZE + (size - header_size).next_multiple_of(PARAGRAPH_SIZE) + extra_paragraphs * PARAGRAPH_SIZE;
+    if memory > CONVENTIONAL_MEMORY {
+        return Err(format!("the program needs {} bytes to load, more than there is conventional memory", memory));
+    }
+
+    Ok(())
+}
+
+/// DOS name for `program`, which has to fit in 8.3 already
+pub fn dos_name(program: &Path) -> Result<String, String> {
+    let name = program.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("{} is not a file", program.display()))?;
+
+    let fits = match name.split_once('.') {
+        Some((stem, extension)) => (1..=8).contains(&stem.len()) && extension.len() <= 3 && !extension.contains('.'),
+        None => (1..=8).contains(&name.len()),
+    };
+
+    if !fits || !name.is_ascii() {
+        return Err(format!("{} is not an 8.3 file name", name));
+    }
+
+    Ok(name.to_ascii_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EXE header for a file of `size` bytes with a two paragraph header
+    fn exe(size: usize, extra_paragraphs: u16) -> Vec<u8> {
+        let mut program = vec![0; size];
+        program[0..2].copy_from_slice(b"MZ");
+        program[2..4].copy_from_slice(&((size % PAGE_SIZE) as u16).to_le_bytes());
+        program[4..6].copy_from_slice(&(size.div_ceil(PAGE_SIZE) as u16).to_le_bytes());
+        program[8..10].copy_from_slice(&2u16.to_le_bytes());
+        program[10..12].copy_from_slice(&extra_paragraphs.to_le_bytes());
+
+        program
+    }
+
+    #[test]
+    fn com_size_limit() {
+        assert!(check(Format::Com, &[0xc3; COM_MAX_SIZE]).is_ok());
+        assert!(check(Format::Com, &[0xc3; COM_MAX_SIZE + 1]).is_err());
+        assert!(check(Format::Com, &[]).is_err());
+    }
+
+    #[test]
+    fn exe_header_matches_file() {
+        assert!(check(Format::Exe, &exe(0x20000, 0x10)).is_ok());
+        assert!(check(Format::Exe, &exe(1000, 0)).is_ok());
+
+        let mut truncated = exe(1000, 0);
+        truncated.truncate(900);
+        assert!(check(Format::Exe, &truncated).is_err());
+        assert!(check(Format::Exe, &[0xc3; 100]).is_err());
+    }
+
+    #[test]
+    fn exe_fits_in_conventional_memory() {
+        assert!(check(Format::Exe, &exe(0x80000, 0x1000)).is_ok());
+        assert!(check(Format::Exe, &exe(0x80000, 0x2000)).is_err());
+    }
+
+    #[test]
+    fn needs_short_names() {
+        assert_eq!(dos_name(Path::new("target/rust_dos.com")), Ok("RUST_DOS.COM".to_string()));
+        assert_eq!(dos_name(Path::new("TESTS")), Ok("TESTS".to_string()));
+        assert!(dos_name(Path::new("really_long_name.com")).is_err());
+        assert!(dos_name(Path::new("a.b.com")).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+//! Booting a disk image in QEMU
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use crate::image;
+
+/// Boot `image` and wait until QEMU is closed. Images without a partition
+/// table are started as a floppy
+pub fn run(image: &Path) -> Result<(), String> {
+    let qemu = env::var("QEMU").unwrap_or_else(|_| "qemu-system-i386".to_string());
+    let floppy = image::image_offset(image)? == 0;
+
+    let (interface, boot) = if floppy { ("floppy", "a") } else { ("ide", "c") };
+
+    Command::new(&qemu)
+        .arg("-drive").arg(format!("file={},format=raw,if={}", image.display(), interface))
+        .arg("-boot").arg(boot)
+        .status()
+        .map(|_| ())
+        .map_err(|error| format!("can't start {}: {}. Set QEMU to its path", qemu, error))
+}
@@ -0,0 +1,79 @@
+//! Reading the TAP report written by `rust_dos.com /tap FILE`
+
+/// What a report says about the run
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    /// Number of tests from the `1..N` plan line, if there was one
+    pub planned: Option<usize>,
+    pub passed: usize,
+    /// Names of the tests that failed
+    pub failed: Vec<String>,
+}
+
+impl Summary {
+    /// Whether every planned test ran and passed. A report that stops early,
+    /// like when the program crashed, is a failure
+    pub fn success(&self) -> bool {
+        self.failed.is_empty() && self.planned == Some(self.passed)
+    }
+}
+
+/// Count the results in a report. Lines that aren't a plan or a result, like
+/// diagnostics, are skipped
+pub fn parse(report: &str) -> Summary {
+    let mut summary = Summary::default();
+
+    for line in report.lines().map(str::trim_end) {
+        if let Some(count) = line.strip_prefix("1..") {
+            summary.planned = count.parse().ok();
+        } else if line.starts_with("ok ") {
+            summary.passed += 1;
+        } else if let Some(result) = line.strip_prefix("not ok ") {
+            summary.failed.push(description(result).to_string());
+        }
+    }
+
+    summary
+}
+
+/// The part of `N - name` after the test number
+fn description(result: &str) -> &str {
+    match result.split_once(" - ") {
+        Some((_, name)) => name,
+        None => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_results() {
+        let report = "TAP version 13\r\n1..3\r\nok 1 - file_test\r\nnot ok 2 - xms_test\r\n\
+            # Exit code 1 (Normal)\r\n# assertion failed\r\nok 3 - ems_test\r\n# 2 passed, 1 failed\r\n";
+
+        let summary = parse(report);
+
+        assert_eq!(summary.planned, Some(3));
+        assert_eq!(summary.passed, 2);
+        assert_eq!(summary.failed, ["xms_test"]);
+        assert!(!summary.success());
+    }
+
+    #[test]
+    fn all_passed() {
+        let summary = parse("TAP version 13\n1..2\nok 1 - a\nok 2 - b\n");
+
+        assert!(summary.success());
+    }
+
+    #[test]
+    fn cut_short_report_fails() {
+        let summary = parse("TAP version 13\n1..2\nok 1 - a\n");
+
+        assert_eq!(summary.passed, 1);
+        assert!(summary.failed.is_empty());
+        assert!(!summary.success());
+    }
+}